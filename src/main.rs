@@ -1,11 +1,188 @@
-use chrono::{TimeZone, Utc};
-use comfy_table::Table;
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, TimeZone, Utc};
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
 use comfy_table::{presets::UTF8_FULL, ContentArrangement};
-use git2::{Branch, BranchType, Commit as GitCommit, ObjectType, Oid, Repository, Tag, Time};
+use comfy_table::{Cell, Color, Table};
+use git2::{
+    BlameOptions, Branch, BranchType, Commit as GitCommit, Delta, Diff, DiffDelta, DiffOptions,
+    ErrorCode, ObjectType, Oid, Patch, Reference, ReferenceType, ReflogEntry, Remote, Repository,
+    Signature, Sort, Status, StatusOptions, Submodule, Tag, Time, TreeWalkMode, TreeWalkResult,
+};
+use regex::Regex;
+use rusqlite::functions::FunctionFlags;
 use rusqlite::params;
-use rusqlite::{types::Value, Connection, Result};
+use rusqlite::{types::Value, Connection, DatabaseName, OptionalExtension, Result};
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{error::ReadlineError, CompletionType, Config, Context, Editor, Helper};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::io::{stdin, stdout, Write};
+use std::fs::File;
+use std::io::{stderr, stdin, stdout, ErrorKind, IsTerminal, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+// Command-line arguments
+#[derive(Parser)]
+#[command(about)]
+struct Args {
+    /// Path to the Git repository to query. Repeat to load several repos into
+    /// one database
+    #[arg(long, default_value = "./")]
+    repo: Vec<String>,
+
+    /// Load the files changed by each commit into the `commit_files` table
+    #[arg(long)]
+    files: bool,
+
+    /// Don't load the history of HEAD, e.g. to only query the refs of a huge repo.
+    /// Commits can still be loaded later with `traverse`
+    #[arg(long)]
+    no_commits: bool,
+
+    /// Don't load the `tags` table
+    #[arg(long)]
+    no_tags: bool,
+
+    /// Don't load the `branches` table
+    #[arg(long)]
+    no_branches: bool,
+
+    /// Create the `commits_fts` table, a full-text index of commit messages
+    #[arg(long)]
+    fts: bool,
+
+    /// Path to a SQLite database file to persist the loaded data in. If it
+    /// was already populated by a previous run, it is reused as is
+    #[arg(long, value_name = "PATH")]
+    db: Option<String>,
+
+    /// Rebuild the database given by `--db`, even if it is already populated
+    #[arg(long, requires = "db")]
+    refresh: bool,
+
+    /// Ref whose reflog is loaded into the `reflog` table
+    #[arg(long, value_name = "REF", default_value = "HEAD")]
+    reflog: String,
+
+    /// Number of characters to abbreviate object ids to. Large repos may
+    /// need more to keep abbreviated ids unambiguous
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_ABBREV, value_parser = clap::value_parser!(u8).range(4..=40))]
+    abbrev: u8,
+
+    /// Load the history in topological order, with children before parents,
+    /// instead of libgit2's default order
+    #[arg(long)]
+    topo_order: bool,
+
+    /// Load the history ordered by commit datetime, newest first
+    #[arg(long)]
+    date_order: bool,
+
+    /// Load the history in reverse order, e.g. oldest first with `--date-order`
+    #[arg(long)]
+    reverse: bool,
+
+    /// Format to display query results in
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+
+    /// Page long query results through `$PAGER` (or `less -FRX`) when
+    /// output goes to a terminal
+    #[arg(long)]
+    pager: bool,
+
+    /// Don't color tables. Tables are colored only when output goes to a
+    /// terminal, and `NO_COLOR` isn't set
+    #[arg(long)]
+    no_color: bool,
+
+    /// Text to display NULL values as, e.g. to tell them apart from empty
+    /// strings. By default, NULL is displayed as `NULL`, and as an empty field in CSV
+    #[arg(long, value_name = "TEXT")]
+    nullvalue: Option<String>,
+
+    /// How to display BLOB values
+    #[arg(long, value_enum, default_value_t = BlobFormat::Hex)]
+    blob_format: BlobFormat,
+
+    /// Maximum number of rows to display in a table, so a query without a
+    /// `LIMIT` doesn't flood the terminal. 0 displays all rows
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_ROWS)]
+    max_rows: usize,
+
+    /// Truncate values longer than N characters, e.g. long commit messages,
+    /// with a trailing `…`. JSON and CSV output always has the full values
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    max_col_width: Option<u16>,
+
+    /// Width of the table to display query results in. By default, the
+    /// table fits the terminal width
+    #[arg(long, value_name = "N")]
+    width: Option<u16>,
+
+    /// SQL query to run when the interactive prompt starts
+    #[arg(long, value_name = "SQL", default_value = INIT_SQL_QUERY)]
+    init_query: String,
+
+    /// Don't run a query when the interactive prompt starts
+    #[arg(long, conflicts_with = "init_query")]
+    no_init_query: bool,
+
+    /// Only display query results, without the initial query or the row
+    /// counts and tips after tables, e.g. for scripts
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Run a single SQL query and exit, instead of starting the interactive prompt
+    #[arg(short, long, value_name = "SQL")]
+    command: Option<String>,
+}
+
+// Enum for the output formats of query results
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Table,
+    Json,
+    Csv,
+    Markdown,
+    Line,
+}
+
+// Enum for the ways of displaying BLOB values
+#[derive(Clone, Copy, ValueEnum)]
+enum BlobFormat {
+    // The bytes as hexadecimal digits
+    Hex,
+    // Only the number of bytes
+    Length,
+}
+
+// Settings for displaying query results, which can be changed during a session
+struct Settings {
+    format: Format,
+    width: Option<u16>,
+    timer: bool,
+    // Text to display NULL values as, if not the default for the format
+    nullvalue: Option<String>,
+    blob_format: BlobFormat,
+    // Maximum number of rows to display in a table, or 0 for all rows
+    max_rows: usize,
+    // Maximum number of characters to display of each value, if any
+    max_col_width: Option<u16>,
+    // Whether to leave out the row count and tips after tables
+    quiet: bool,
+    // Command to page long results through, if paging is enabled
+    pager: Option<String>,
+    // Whether to color the values in tables by type
+    color: bool,
+    // File to write query results to instead of the terminal, set with `.output`
+    output: Option<File>,
+}
 
 // Enum for errors
 #[derive(Debug)]
@@ -23,6 +200,34 @@ impl fmt::Display for Error {
     }
 }
 
+impl Error {
+    // Function to get the exit code for a fatal error of this kind
+    fn exit_code(&self) -> i32 {
+        match self {
+            Self::GitError(_) => EXIT_GIT_ERROR,
+            Self::SqlError(_) => EXIT_SQL_ERROR,
+        }
+    }
+}
+
+// Exit codes for fatal errors, so scripts can tell their kinds apart. 2 is left out, as
+// clap exits with it for invalid command-line arguments
+const EXIT_GIT_ERROR: i32 = 1;
+const EXIT_IO_ERROR: i32 = 3;
+const EXIT_SQL_ERROR: i32 = 4;
+
+// A fatal error, which ends the program with its exit code
+struct Fatal {
+    message: String,
+    exit_code: i32,
+}
+
+impl Fatal {
+    fn new(exit_code: i32, message: String) -> Self {
+        Fatal { message, exit_code }
+    }
+}
+
 impl From<git2::Error> for Error {
     fn from(err: git2::Error) -> Self {
         Error::GitError(err)
@@ -37,43 +242,294 @@ impl From<rusqlite::Error> for Error {
 
 // Enum to support both annotated and lightweight git tags
 enum GitTag<'a> {
-    Annotated(Tag<'a>),
+    // The tag object, and the name of the ref that points to it
+    Annotated(Tag<'a>, String),
     Lightweight {
         id: Oid,
-        name: Option<String>,
+        name: String,
         target_id: Oid,
+        target_type: Option<ObjectType>,
+        // Authored time of the target, if it is a commit
+        target_time: Option<Time>,
     },
 }
 
+// Function to convert a Git time to an ISO 8601 String, keeping its original timezone offset.
+// Returns None if the time or offset is out of range (e.g., in corrupt or old imported repos)
+fn time_to_string(time: Time) -> Option<String> {
+    let offset = FixedOffset::east_opt(time.offset_minutes() * 60)?;
+
+    offset
+        .timestamp_opt(time.seconds(), 0)
+        .single()
+        .map(|datetime| datetime.to_rfc3339())
+}
+
+// Function to diff a Git commit against its first parent, optionally with options such as
+// a pathspec to limit the diff to
+fn diff_commit<'a>(
+    repo: &'a Repository,
+    commit: &GitCommit,
+    options: Option<&mut DiffOptions>,
+) -> Result<Diff<'a>, Error> {
+    // Diff against an empty tree for root commits
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+
+    Ok(repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), options)?)
+}
+
+// Markers that begin each type of signature supported by Git, and the name of the type
+const SIGNATURE_MARKERS: &[(&str, &str)] = &[
+    ("-----BEGIN PGP SIGNATURE-----", "gpg"),
+    ("-----BEGIN SSH SIGNATURE-----", "ssh"),
+    ("-----BEGIN SIGNED MESSAGE-----", "x509"),
+];
+
+// Function to determine the type of a signature from its marker
+fn signature_type(signature: &str) -> &'static str {
+    SIGNATURE_MARKERS
+        .iter()
+        .find(|(marker, _)| signature.contains(marker))
+        .map_or("unknown", |(_, sig_type)| sig_type)
+}
+
+// Default number of characters that object ids are abbreviated to
+const DEFAULT_ABBREV: u8 = 7;
+
+// Default maximum number of rows to display in a table
+const DEFAULT_MAX_ROWS: usize = 1000;
+
+// Context for inserting the Git data of a repo into the SQLite database
+struct LoadContext<'a> {
+    // Name of the repo, stored in the `repo` column of every table
+    repo_name: &'a str,
+    // Number of characters that object ids are abbreviated to
+    abbrev_len: usize,
+    // Whether to show the number of commits loaded so far, for long loads
+    show_progress: bool,
+}
+
+// Function to get the name of a repo: the name of its working directory, or of the
+// Git directory itself for a bare repo
+fn repo_name(repo: &Repository) -> String {
+    let path = repo.workdir().unwrap_or(repo.path());
+
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string())
+}
+
+// Number of commits loaded between updates of the progress count
+const PROGRESS_INTERVAL: usize = 1000;
+
+// Function to check whether to show progress during loads: only on a terminal, so it
+// doesn't end up in redirected output, and not with `--quiet`
+fn show_progress(args: &Args) -> bool {
+    !args.quiet && stderr().is_terminal()
+}
+
+// Function to show the number of commits walked so far, if progress is shown. The count is
+// overwritten on the same line, so it doesn't scroll
+fn report_progress(ctx: &LoadContext, label: &str, count: usize) {
+    if ctx.show_progress && count > 0 && count.is_multiple_of(PROGRESS_INTERVAL) {
+        eprint!("\r{}... {}", label, count);
+    }
+}
+
+// Function to clear the progress count, if it was shown
+fn clear_progress(ctx: &LoadContext) {
+    if ctx.show_progress {
+        eprint!("\r\x1b[K");
+    }
+}
+
+// Function to abbreviate an object id to its first `len` characters
+fn abbrev(id: Oid, len: usize) -> String {
+    id.to_string().chars().take(len).collect()
+}
+
+// Function to get the name of a signature, so attribution isn't lost for a malformed
+// signature: a name that isn't valid UTF-8 is decoded lossily, and a missing name falls
+// back to the email. Returns None if both are missing
+fn signature_name(signature: &Signature) -> Option<String> {
+    [signature.name_bytes(), signature.email_bytes()]
+        .into_iter()
+        .find(|bytes| !bytes.is_empty())
+        .map(|bytes| String::from_utf8_lossy(bytes).to_string())
+}
+
 // Function to insert a Git commit into the SQLite database
-fn insert_commit(conn: &Connection, commit: &GitCommit) -> Result<(), Error> {
-    // Extract the commit datetime in UTC
-    let datetime = Utc.timestamp_opt(commit.time().seconds(), 0);
+fn insert_commit(
+    conn: &Connection,
+    repo: &Repository,
+    commit: &GitCommit,
+    diff: &Diff,
+    ctx: &LoadContext,
+) -> Result<(), Error> {
+    // Extract the commit author and the authored datetime
+    let author = commit.author();
+    let datetime = time_to_string(author.when());
 
-    // Execute the SQL INSERT statement
-    conn.execute(
-        "INSERT OR IGNORE INTO commits (id, author, date, message) VALUES (?1, ?2, ?3, ?4)",
+    // Extract the committer and the committed datetime
+    let committer = commit.committer();
+    let committer_datetime = time_to_string(committer.when());
+
+    // Extract the number of lines added and removed by the commit
+    let stats = diff.stats()?;
+
+    // Extract the type of the commit's signature, if it is signed
+    let sig_type = match repo.extract_signature(&commit.id(), None) {
+        Ok((signature, _)) => Some(signature_type(&String::from_utf8_lossy(&signature))),
+        Err(err) if err.code() == ErrorCode::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+
+    // Execute the SQL INSERT statement. The statement is prepared once and cached,
+    // rather than parsed again for every commit
+    conn.prepare_cached(
+        "INSERT OR IGNORE INTO commits (id, full_id, author, author_email, date, message, subject, body, committer, committer_email, committer_date, insertions, deletions, is_signed, signature_type, tree_id, date_epoch, committer_date_epoch, parent_count, repo) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
+    )?
+    .execute(
         params![
-            // Store only the first 7 characters of the commit id
-            commit.id().to_string().chars().take(7).collect::<String>(),
-            commit.author().name(),
-            datetime.unwrap().to_string(),
-            commit.message(),
+            abbrev(commit.id(), ctx.abbrev_len),
+            // Also store the full commit id, which is unambiguous for joins
+            commit.id().to_string(),
+            signature_name(&author),
+            // NULL if the email is absent or not valid UTF-8
+            author.email(),
+            datetime,
+            // Messages that aren't valid UTF-8 (e.g., Latin-1 messages in old repos) are decoded
+            // lossily, rather than lost
+            String::from_utf8_lossy(commit.message_bytes()),
+            // The subject is the first paragraph of the message, and the body is the remainder
+            commit.summary_bytes().map(String::from_utf8_lossy),
+            commit.body_bytes().map(String::from_utf8_lossy),
+            signature_name(&committer),
+            committer.email(),
+            committer_datetime,
+            stats.insertions(),
+            stats.deletions(),
+            sig_type.is_some(),
+            sig_type,
+            // Store the full tree id
+            commit.tree_id().to_string(),
+            // Also store the datetimes as seconds since the epoch, for arithmetic
+            author.when().seconds(),
+            committer.when().seconds(),
+            // More than one parent for a merge, and none for a root commit
+            commit.parent_count(),
+            ctx.repo_name,
         ],
     )?;
 
+    // Insert the commit's parents, in order (index 0 is the first parent). They are keyed by
+    // the full ids, so commits whose abbreviated ids collide keep their own parents
+    for (parent_index, parent_id) in commit.parent_ids().enumerate() {
+        conn.prepare_cached(
+            "INSERT OR IGNORE INTO commit_parents (commit_id, commit_full_id, parent_id, parent_full_id, parent_index, repo) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?
+        .execute(
+            params![
+                abbrev(commit.id(), ctx.abbrev_len),
+                commit.id().to_string(),
+                abbrev(parent_id, ctx.abbrev_len),
+                parent_id.to_string(),
+                parent_index,
+                ctx.repo_name,
+            ],
+        )?;
+    }
+
     Ok(())
 }
 
-// Function to remove PGP signature from message
-fn remove_pgp_signature(message: &str) -> String {
-    let begin_pgp_marker = "-----BEGIN PGP SIGNATURE-----";
+// Function to convert a diff delta status to a String
+fn delta_status_to_string(status: Delta) -> String {
+    match status {
+        Delta::Added => "added",
+        Delta::Deleted => "deleted",
+        Delta::Modified => "modified",
+        Delta::Renamed => "renamed",
+        Delta::Copied => "copied",
+        Delta::Typechange => "typechange",
+        Delta::Unmodified => "unmodified",
+        Delta::Ignored => "ignored",
+        Delta::Untracked => "untracked",
+        Delta::Unreadable => "unreadable",
+        Delta::Conflicted => "conflicted",
+    }
+    .to_string()
+}
+
+// Function to get the path of a file changed in a diff
+fn delta_path(delta: &DiffDelta) -> Option<String> {
+    // Use the old path for deleted files, as they have no new path
+    let file = match delta.status() {
+        Delta::Deleted => delta.old_file(),
+        _ => delta.new_file(),
+    };
+
+    file.path().map(|p| p.to_string_lossy().to_string())
+}
+
+// Function to count the lines added and removed in a file changed in a diff
+fn delta_line_stats(
+    diff: &Diff,
+    delta_idx: usize,
+) -> Result<(Option<usize>, Option<usize>), Error> {
+    // Binary files have no patch, and so no line stats
+    match Patch::from_diff(diff, delta_idx)? {
+        Some(patch) => {
+            let (_, insertions, deletions) = patch.line_stats()?;
+            Ok((Some(insertions), Some(deletions)))
+        }
+        None => Ok((None, None)),
+    }
+}
+
+// Function to insert the files changed by a Git commit into the SQLite database
+fn insert_commit_files(
+    conn: &Connection,
+    commit: &GitCommit,
+    diff: &Diff,
+    ctx: &LoadContext,
+) -> Result<(), Error> {
+    for (delta_idx, delta) in diff.deltas().enumerate() {
+        let path = delta_path(&delta);
+        let (insertions, deletions) = delta_line_stats(diff, delta_idx)?;
 
-    // Find the position of the PGP marker
-    let end_pos = message.find(begin_pgp_marker);
+        conn.prepare_cached(
+            "INSERT OR IGNORE INTO commit_files (commit_id, commit_full_id, path, status, insertions, deletions, repo) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?
+        .execute(
+            params![
+                abbrev(commit.id(), ctx.abbrev_len),
+                commit.id().to_string(),
+                path,
+                delta_status_to_string(delta.status()),
+                insertions,
+                deletions,
+                ctx.repo_name,
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+// Function to remove a signature (PGP, SSH or X.509) from message
+fn strip_signature(message: &str) -> String {
+    // Find the position of the earliest signature marker
+    let end_pos = SIGNATURE_MARKERS
+        .iter()
+        .filter_map(|(marker, _)| message.find(marker))
+        .min();
 
     if let Some(e_pos) = end_pos {
-        // Take a substring ending with the position of the PGP marker
+        // Take a substring ending with the position of the signature marker
         let modified_message = message[..e_pos].trim().to_string();
 
         modified_message
@@ -82,31 +538,42 @@ fn remove_pgp_signature(message: &str) -> String {
     }
 }
 
-// Function to insert a Git tag into the SQLite database
-fn insert_tag(conn: &Connection, tag: GitTag) -> Result<(), Error> {
+// Function to insert a Git tag into the SQLite database. Tags are keyed by name, as a
+// lightweight tag's id is the id of its target, which other tags can share. A tag whose name
+// is already present is ignored, so a duplicate doesn't stop the remaining tags from loading
+fn insert_tag(conn: &Connection, tag: GitTag, ctx: &LoadContext) -> Result<(), Error> {
     match tag {
-        GitTag::Annotated(t) => {
+        GitTag::Annotated(t, name) => {
             let tagger: Option<String> = t
                 .tagger()
                 .and_then(|sig| sig.name().map(|name| name.to_string()));
 
-            let date = t
-                .tagger()
-                .map(|sig| sig.when())
-                .map(|time: Time| Utc.timestamp_opt(time.seconds(), 0).unwrap().to_string());
+            let date = t.tagger().and_then(|sig| time_to_string(sig.when()));
+
+            // Peel the tag (through any tags of tags) to the commit it marks, if any
+            let target_time = t
+                .peel()
+                .ok()
+                .and_then(|object| object.into_commit().ok())
+                .map(|commit| commit.author().when());
 
             conn.execute(
-                "INSERT INTO tags (id, name, target_id, target_type, tagger, date, message) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                "INSERT OR IGNORE INTO tags (id, name, target_id, target_full_id, target_type, tagger, date, message, date_epoch, is_annotated, target_date, target_date_epoch, repo) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
                 params![
-                    // Store only the first 7 characters of the tag id
-                    t.id().to_string().chars().take(7).collect::<String>(),
-                    t.name(),
-                    // Store only the first 7 characters of the tag target id
-                    t.target_id().to_string().chars().take(7).collect::<String>(),
+                    abbrev(t.id(), ctx.abbrev_len),
+                    name,
+                    abbrev(t.target_id(), ctx.abbrev_len),
+                    t.target_id().to_string(),
                     t.target_type().map(|t_type| t_type.to_string()),
                     tagger,
                     date,
-                    t.message().map(remove_pgp_signature),
+                    t.message_bytes()
+                        .map(|m| strip_signature(&String::from_utf8_lossy(m))),
+                    t.tagger().map(|sig| sig.when().seconds()),
+                    true,
+                    target_time.and_then(time_to_string),
+                    target_time.map(|time| time.seconds()),
+                    ctx.repo_name,
                 ],
             )?;
         }
@@ -114,16 +581,21 @@ fn insert_tag(conn: &Connection, tag: GitTag) -> Result<(), Error> {
             id,
             name,
             target_id,
+            target_type,
+            target_time,
         } => {
             conn.execute(
-                "INSERT INTO tags (id, name, target_id, target_type) VALUES (?1, ?2, ?3, ?4)",
+                "INSERT OR IGNORE INTO tags (id, name, target_id, target_full_id, target_type, is_annotated, target_date, target_date_epoch, repo) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
                 params![
-                    // Store only the first 7 characters of the tag id
-                    id.to_string().chars().take(7).collect::<String>(),
+                    abbrev(id, ctx.abbrev_len),
                     name,
-                    // Store only the first 7 characters of the tag target id
-                    target_id.to_string().chars().take(7).collect::<String>(),
-                    ObjectType::Commit.to_string(),
+                    abbrev(target_id, ctx.abbrev_len),
+                    target_id.to_string(),
+                    target_type.map(|t_type| t_type.to_string()),
+                    false,
+                    target_time.and_then(time_to_string),
+                    target_time.map(|time| time.seconds()),
+                    ctx.repo_name,
                 ],
             )?;
         }
@@ -132,47 +604,338 @@ fn insert_tag(conn: &Connection, tag: GitTag) -> Result<(), Error> {
     Ok(())
 }
 
-// Function to insert a Git branch into the SQLite database
-fn insert_branch(conn: &Connection, branch: Branch, branch_type: BranchType) -> Result<(), Error> {
+// Function to insert a Git remote into the SQLite database
+fn insert_remote(conn: &Connection, remote: &Remote, ctx: &LoadContext) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO remotes (name, url, push_url, repo) VALUES (?1, ?2, ?3, ?4)",
+        // The push URL is NULL if the remote pushes to its fetch URL
+        params![remote.name(), remote.url(), remote.pushurl(), ctx.repo_name],
+    )?;
+
+    Ok(())
+}
+
+// Function to insert a Git stash into the SQLite database
+fn insert_stash(
+    conn: &Connection,
+    index: usize,
+    message: &str,
+    id: Oid,
+    ctx: &LoadContext,
+) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO stashes (stash_index, message, commit_id, commit_full_id, repo) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![index, message, abbrev(id, ctx.abbrev_len), id.to_string(), ctx.repo_name],
+    )?;
+
+    Ok(())
+}
+
+// Function to insert a Git submodule into the SQLite database
+fn insert_submodule(
+    conn: &Connection,
+    submodule: &Submodule,
+    ctx: &LoadContext,
+) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO submodules (name, path, url, head_id, repo) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            submodule.name(),
+            submodule.path().to_string_lossy(),
+            submodule.url(),
+            // The commit the superproject's HEAD records for the submodule, if any
+            submodule.head_id().map(|id| id.to_string()),
+            ctx.repo_name,
+        ],
+    )?;
+
+    Ok(())
+}
+
+// Function to insert an entry of a reflog into the SQLite database
+fn insert_reflog_entry(
+    conn: &Connection,
+    ref_name: &str,
+    index: usize,
+    entry: &ReflogEntry,
+    ctx: &LoadContext,
+) -> Result<(), Error> {
+    let committer = entry.committer();
+
+    conn.execute(
+        "INSERT INTO reflog (ref_name, reflog_index, old_id, old_full_id, new_id, new_full_id, committer, date, message, repo) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            ref_name,
+            index,
+            abbrev(entry.id_old(), ctx.abbrev_len),
+            entry.id_old().to_string(),
+            abbrev(entry.id_new(), ctx.abbrev_len),
+            entry.id_new().to_string(),
+            committer.name(),
+            time_to_string(committer.when()),
+            entry.message(),
+            ctx.repo_name,
+        ],
+    )?;
+
+    Ok(())
+}
+
+// Function to insert a Git branch into the SQLite database. A branch type of None
+// is a detached HEAD, which is inserted as a branch with no name
+fn insert_branch(
+    conn: &Connection,
+    repo: &Repository,
+    branch: Branch,
+    branch_type: Option<BranchType>,
+    ctx: &LoadContext,
+) -> Result<(), Error> {
     let reference = branch.get();
     let head_commit = reference.peel_to_commit().ok();
-    let head_commit_id = head_commit
+    let head_commit_full_id = head_commit.as_ref().map(|h| h.id().to_string());
+    let head_commit_id = head_commit.as_ref().map(|h| abbrev(h.id(), ctx.abbrev_len));
+    let head_commit_date = head_commit.as_ref().and_then(|h| time_to_string(h.time()));
+    let head_commit_date_epoch = head_commit.as_ref().map(|h| h.time().seconds());
+
+    // The upstream is NULL for branches that don't track one, including remote branches
+    let upstream_branch = branch.upstream().ok();
+    let upstream = upstream_branch
         .as_ref()
-        .map(|h| h.id().to_string().chars().take(7).collect::<String>());
-    let head_commit_date = head_commit.as_ref().map(|h| {
-        Utc.timestamp_opt(h.time().seconds(), 0)
-            .unwrap()
-            .to_string()
-    });
+        .and_then(|u| u.name().ok().flatten().map(|name| name.to_string()));
+
+    // Count the commits the branch is ahead of and behind its upstream
+    let (ahead, behind) = match (
+        reference.target(),
+        upstream_branch.as_ref().and_then(|u| u.get().target()),
+    ) {
+        (Some(local_oid), Some(upstream_oid)) => {
+            let (a, b) = repo.graph_ahead_behind(local_oid, upstream_oid)?;
+            (Some(a), Some(b))
+        }
+        _ => (None, None),
+    };
 
+    // Replace any existing row for the branch, so reloading it keeps its latest state
     conn.execute(
-        "INSERT INTO branches (name, type, head_commit_id, head_commit_date) VALUES (?1, ?2, ?3, ?4)",
+        "INSERT OR REPLACE INTO branches (name, type, head_commit_id, head_commit_full_id, head_commit_date, upstream, ahead, behind, head_commit_date_epoch, is_head, repo) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
         params![
-            branch.name().ok(),
+            branch.name().ok().flatten(),
             match branch_type {
-                BranchType::Local => "local",
-                BranchType::Remote => "remote",
+                Some(BranchType::Local) => "local",
+                Some(BranchType::Remote) => "remote",
+                None => "detached",
             },
             head_commit_id,
-            head_commit_date
+            head_commit_full_id,
+            head_commit_date,
+            upstream,
+            ahead,
+            behind,
+            head_commit_date_epoch,
+            // Whether the branch is checked out
+            branch_type.is_none() || branch.is_head(),
+            ctx.repo_name,
         ],
     )?;
 
     Ok(())
 }
 
-// Function to initialize the SQLite database with Git commit data
-fn init_db(repo: &Repository) -> Result<Connection, Error> {
-    // Open an in-memory SQLite database
-    let conn = Connection::open_in_memory()?;
+// Function to insert information about the repo into the SQLite database
+fn insert_meta(conn: &Connection, repo: &Repository, ctx: &LoadContext) -> Result<(), Error> {
+    // HEAD refers to a branch (even before its first commit), or directly to a commit when detached
+    let head_ref = repo.find_reference("HEAD")?;
+    let head = match head_ref.symbolic_target() {
+        Some(target) => Some(target.to_string()),
+        None => head_ref.target().map(|id| id.to_string()),
+    };
+
+    // The default branch is the branch that origin's HEAD refers to, e.g. `origin/main`
+    let default_branch = repo
+        .find_reference("refs/remotes/origin/HEAD")
+        .ok()
+        .and_then(|r| r.symbolic_target().map(|t| t.to_string()))
+        .map(|t| t.strip_prefix("refs/remotes/").unwrap_or(&t).to_string());
+
+    let commit_count: i64 = conn.query_row(
+        "SELECT count(*) FROM commits WHERE repo = ?1",
+        params![ctx.repo_name],
+        |row| row.get(0),
+    )?;
+
+    // A bare repo has no working directory, so its path is the Git directory itself
+    let path = repo.workdir().unwrap_or(repo.path()).to_string_lossy();
+
+    let mut stmt = conn.prepare("INSERT INTO meta (key, value, repo) VALUES (?1, ?2, ?3)")?;
+    stmt.execute(params!["path", path, ctx.repo_name])?;
+    stmt.execute(params!["is_bare", repo.is_bare(), ctx.repo_name])?;
+    stmt.execute(params!["head", head, ctx.repo_name])?;
+    stmt.execute(params!["default_branch", default_branch, ctx.repo_name])?;
+    stmt.execute(params!["commit_count", commit_count, ctx.repo_name])?;
+
+    Ok(())
+}
+
+// Names of the tables populated with Git data
+const TABLES: &[&str] = &[
+    "commits",
+    "commit_parents",
+    "commit_files",
+    "tags",
+    "branches",
+    "remotes",
+    "stashes",
+    "submodules",
+    "reflog",
+    "commits_fts",
+    "blobs",
+    "diffs",
+    "meta",
+    "refs",
+    "blame",
+    "status",
+    "path_history",
+    "signatures",
+];
+
+// Name of the table with git-query's own bookkeeping (e.g., how the database was loaded),
+// which is kept apart from the Git data and left out of `.tables`
+const INTERNAL_TABLE: &str = "_git_query";
+
+// Function to describe what is loaded into the database: the repos, and the options that
+// change the loaded data. A database is only reused if it was loaded the same way
+fn load_options(repos: &[Repository], args: &Args) -> String {
+    let paths: Vec<String> = repos
+        .iter()
+        .map(|repo| repo.path().to_string_lossy().to_string())
+        .collect();
+
+    serde_json::json!({
+        "repos": paths,
+        "abbrev": args.abbrev,
+        "files": args.files,
+        "fts": args.fts,
+        "no_commits": args.no_commits,
+        "no_tags": args.no_tags,
+        "no_branches": args.no_branches,
+        "reflog": args.reflog,
+        "topo_order": args.topo_order,
+        "date_order": args.date_order,
+        "reverse": args.reverse,
+    })
+    .to_string()
+}
+
+// Function to get how a previously populated database was loaded, if it was
+fn stored_load_options(conn: &Connection) -> Result<Option<String>, Error> {
+    let has_internal_table: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+        [INTERNAL_TABLE],
+        |row| row.get(0),
+    )?;
+
+    if !has_internal_table {
+        return Ok(None);
+    }
+
+    let options = conn
+        .query_row(
+            &format!(
+                "SELECT value FROM {} WHERE key = 'load_options'",
+                INTERNAL_TABLE
+            ),
+            (),
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    Ok(options)
+}
+
+// Function to run database changes in a transaction, which is rolled back if they fail
+fn in_transaction<T>(
+    conn: &Connection,
+    f: impl FnOnce(&Connection) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let tx = conn.unchecked_transaction()?;
+    let result = f(&tx)?;
+    tx.commit()?;
+
+    Ok(result)
+}
+
+// Function to create the tables for the Git data
+fn create_tables(conn: &Connection) -> Result<(), Error> {
+    // Drop any tables left over from a previous run
+    for table in TABLES.iter().chain([&INTERNAL_TABLE]) {
+        conn.execute(&format!("DROP TABLE IF EXISTS {}", table), ())?;
+    }
+
+    // Create the internal table, with a row per `key`
+    conn.execute(
+        &format!(
+            "CREATE TABLE {} (
+                        key   TEXT PRIMARY KEY,
+                        value
+                    )",
+            INTERNAL_TABLE
+        ),
+        (),
+    )?;
 
     // Create the 'commits' table
     conn.execute(
         "CREATE TABLE commits (
-                        id       TEXT PRIMARY KEY,
-                        author   TEXT,
-                        date     TEXT NOT NULL,
-                        message  TEXT
+                        repo                 TEXT NOT NULL,
+                        id                   TEXT NOT NULL,
+                        full_id              TEXT NOT NULL,
+                        author               TEXT,
+                        author_email         TEXT,
+                        date                 TEXT,
+                        message              TEXT,
+                        subject              TEXT,
+                        body                 TEXT,
+                        committer            TEXT,
+                        committer_email      TEXT,
+                        committer_date       TEXT,
+                        insertions           INTEGER,
+                        deletions            INTEGER,
+                        is_signed            INTEGER NOT NULL,
+                        signature_type       TEXT,
+                        tree_id              TEXT NOT NULL,
+                        date_epoch           INTEGER NOT NULL,
+                        committer_date_epoch INTEGER NOT NULL,
+                        parent_count         INTEGER NOT NULL,
+                        PRIMARY KEY (repo, full_id)
+                    )",
+        (),
+    )?;
+
+    // Create the 'commit_parents' table
+    conn.execute(
+        "CREATE TABLE commit_parents (
+                        repo           TEXT NOT NULL,
+                        commit_id      TEXT NOT NULL,
+                        commit_full_id TEXT NOT NULL,
+                        parent_id      TEXT NOT NULL,
+                        parent_full_id TEXT NOT NULL,
+                        parent_index   INTEGER NOT NULL,
+                        PRIMARY KEY (repo, commit_full_id, parent_index)
+                    )",
+        (),
+    )?;
+
+    // Create the 'commit_files' table
+    conn.execute(
+        "CREATE TABLE commit_files (
+                        repo           TEXT NOT NULL,
+                        commit_id      TEXT NOT NULL,
+                        commit_full_id TEXT NOT NULL,
+                        path           TEXT NOT NULL,
+                        status         TEXT,
+                        insertions     INTEGER,
+                        deletions      INTEGER,
+                        PRIMARY KEY (repo, commit_full_id, path)
                     )",
         (),
     )?;
@@ -180,13 +943,20 @@ fn init_db(repo: &Repository) -> Result<Connection, Error> {
     // Create the 'tags' table
     conn.execute(
         "CREATE TABLE tags (
-                        id          TEXT PRIMARY KEY,
-                        name        TEXT,
-                        target_id   TEXT NOT NULL,
-                        target_type TEXT,
-                        tagger      TEXT,
-                        date        TEXT,
-                        message     TEXT
+                        repo              TEXT NOT NULL,
+                        id                TEXT NOT NULL,
+                        name              TEXT NOT NULL,
+                        target_id         TEXT NOT NULL,
+                        target_full_id    TEXT NOT NULL,
+                        target_type       TEXT,
+                        tagger            TEXT,
+                        date              TEXT,
+                        message           TEXT,
+                        date_epoch        INTEGER,
+                        is_annotated      INTEGER NOT NULL,
+                        target_date       TEXT,
+                        target_date_epoch INTEGER,
+                        PRIMARY KEY (repo, name)
                     )",
         (),
     )?;
@@ -194,203 +964,2747 @@ fn init_db(repo: &Repository) -> Result<Connection, Error> {
     // Create the 'branches' table
     conn.execute(
         "CREATE TABLE branches (
-                        name             TEXT,
-                        type             TEXT,
-                        head_commit_id   TEXT,
-                        head_commit_date TEXT
+                        repo                   TEXT NOT NULL,
+                        name                   TEXT,
+                        type                   TEXT,
+                        head_commit_id         TEXT,
+                        head_commit_full_id    TEXT,
+                        head_commit_date       TEXT,
+                        upstream               TEXT,
+                        ahead                  INTEGER,
+                        behind                 INTEGER,
+                        head_commit_date_epoch INTEGER,
+                        is_head                INTEGER NOT NULL,
+                        PRIMARY KEY (repo, name, type)
                     )",
         (),
     )?;
 
-    traverse(&conn, &repo, None)?;
+    // Create the 'remotes' table
+    conn.execute(
+        "CREATE TABLE remotes (
+                        repo     TEXT NOT NULL,
+                        name     TEXT NOT NULL,
+                        url      TEXT,
+                        push_url TEXT,
+                        PRIMARY KEY (repo, name)
+                    )",
+        (),
+    )?;
 
-    let mut tag_sql_error: Option<Error> = None;
+    // Create the 'stashes' table
+    conn.execute(
+        "CREATE TABLE stashes (
+                        repo           TEXT NOT NULL,
+                        stash_index    INTEGER NOT NULL,
+                        message        TEXT,
+                        commit_id      TEXT NOT NULL,
+                        commit_full_id TEXT NOT NULL,
+                        PRIMARY KEY (repo, stash_index)
+                    )",
+        (),
+    )?;
 
-    // Insert tags
-    repo.tag_foreach(|id, name| {
-        let tag = repo.find_tag(id);
+    // Create the 'submodules' table
+    conn.execute(
+        "CREATE TABLE submodules (
+                        repo    TEXT NOT NULL,
+                        name    TEXT,
+                        path    TEXT NOT NULL,
+                        url     TEXT,
+                        head_id TEXT,
+                        PRIMARY KEY (repo, path)
+                    )",
+        (),
+    )?;
 
-        match tag {
-            // Annotated tag
-            Ok(t) => {
-                if let Err(err) = insert_tag(&conn, GitTag::Annotated(t)) {
-                    tag_sql_error = Some(err);
-                    return false; // Stop iterating over tags
-                }
-            }
-            // Lightweight tag
-            _ => {
-                let n: Option<String> = std::str::from_utf8(name)
-                    .map(|s| s.to_string())
-                    .ok()
-                    // Remove "refs/tags/" prefix, if present
-                    .map(|s| s.strip_prefix("refs/tags/").unwrap_or(&s).to_string());
-
-                if let Err(err) = insert_tag(
-                    &conn,
-                    GitTag::Lightweight {
-                        id,
-                        name: n,
-                        target_id: id,
-                    },
-                ) {
-                    tag_sql_error = Some(err);
-                    return false; // Stop iterating over tags
-                }
-            }
-        };
+    // Create the 'blobs' table, which is populated by the `load-tree` command
+    conn.execute(
+        "CREATE TABLE blobs (
+                        repo           TEXT NOT NULL,
+                        commit_id      TEXT NOT NULL,
+                        commit_full_id TEXT NOT NULL,
+                        path           TEXT NOT NULL,
+                        size           INTEGER NOT NULL,
+                        is_binary      INTEGER NOT NULL,
+                        PRIMARY KEY (repo, commit_full_id, path)
+                    )",
+        (),
+    )?;
 
-        // Continue iterating over tags
-        true
-    })?;
+    // Create the 'diffs' table, which is populated by the `diff` command
+    conn.execute(
+        "CREATE TABLE diffs (
+                        repo       TEXT NOT NULL,
+                        rev_a      TEXT NOT NULL,
+                        rev_b      TEXT NOT NULL,
+                        path       TEXT NOT NULL,
+                        status     TEXT,
+                        insertions INTEGER,
+                        deletions  INTEGER,
+                        PRIMARY KEY (repo, rev_a, rev_b, path)
+                    )",
+        (),
+    )?;
 
-    if let Some(tag_sql_err) = tag_sql_error {
-        return Err(tag_sql_err);
-    }
+    // Create the 'refs' table, which is populated by the `refs` command
+    conn.execute(
+        "CREATE TABLE refs (
+                        repo            TEXT NOT NULL,
+                        name            TEXT NOT NULL,
+                        target_id       TEXT,
+                        symbolic_target TEXT,
+                        type            TEXT NOT NULL,
+                        PRIMARY KEY (repo, name)
+                    )",
+        (),
+    )?;
 
-    // Insert branches
-    for branch in repo.branches(None)? {
-        let b = branch?;
-        insert_branch(&conn, b.0, b.1)?;
-    }
+    // Create the 'blame' table, which is populated by the `blame` command
+    conn.execute(
+        "CREATE TABLE blame (
+                        repo                 TEXT NOT NULL,
+                        rev                  TEXT NOT NULL,
+                        path                 TEXT NOT NULL,
+                        line_no              INTEGER NOT NULL,
+                        final_commit_id      TEXT NOT NULL,
+                        final_commit_full_id TEXT NOT NULL,
+                        orig_line_no         INTEGER NOT NULL,
+                        author               TEXT,
+                        PRIMARY KEY (repo, rev, path, line_no)
+                    )",
+        (),
+    )?;
 
-    Ok(conn)
-}
+    // Create the 'status' table, which is populated by the `status` command
+    conn.execute(
+        "CREATE TABLE status (
+                        repo   TEXT NOT NULL,
+                        path   TEXT NOT NULL,
+                        status TEXT NOT NULL,
+                        PRIMARY KEY (repo, path)
+                    )",
+        (),
+    )?;
 
-// Function to convert SQLite Value to a String
-fn value_to_string(value: Value) -> String {
-    match value {
-        Value::Integer(i) => i.to_string(),
-        Value::Real(f) => f.to_string(),
-        // Replace \r\n with \n, as \r\n causes formatting issues with table
-        Value::Text(s) => s.replace("\r\n", "\n"),
-        Value::Blob(_) => String::from("Blob"),
-        Value::Null => String::from("NULL"),
-    }
-}
+    // Create the 'path_history' table, which is populated by the `log` command
+    conn.execute(
+        "CREATE TABLE path_history (
+                        repo           TEXT NOT NULL,
+                        commit_id      TEXT NOT NULL,
+                        commit_full_id TEXT NOT NULL,
+                        path           TEXT NOT NULL,
+                        status         TEXT NOT NULL,
+                        PRIMARY KEY (repo, commit_full_id, path)
+                    )",
+        (),
+    )?;
 
-// Function to run an SQL query and display the results in a table
-fn run_sql_query(conn: &Connection, sql: &str) -> Result<(), Error> {
-    let mut stmt = conn.prepare(sql)?;
-    let column_names: Vec<&str> = stmt.column_names().into_iter().collect();
-    let column_len = column_names.len();
+    // Create the 'signatures' table, which is populated by the `verify` command
+    conn.execute(
+        "CREATE TABLE signatures (
+                        repo           TEXT NOT NULL,
+                        commit_id      TEXT NOT NULL,
+                        commit_full_id TEXT NOT NULL,
+                        signature_type TEXT NOT NULL,
+                        committer       TEXT,
+                        committer_email TEXT,
+                        signature      TEXT NOT NULL,
+                        signed_data    TEXT NOT NULL,
+                        PRIMARY KEY (repo, commit_full_id)
+                    )",
+        (),
+    )?;
 
-    // Create a comfy_table for displaying query results
-    let mut table = Table::new();
-    table
-        .load_preset(UTF8_FULL)
-        .set_content_arrangement(ContentArrangement::Dynamic)
-        // TODO: make table width configurable
-        // .set_width(80)
-        .set_header(&column_names);
+    // Create the 'meta' table
+    conn.execute(
+        "CREATE TABLE meta (
+                        repo  TEXT NOT NULL,
+                        key   TEXT NOT NULL,
+                        value,
+                        PRIMARY KEY (repo, key)
+                    )",
+        (),
+    )?;
 
-    // Execute the SQL query
-    let mut rows = stmt.query([])?;
-    let mut row_count = 0;
+    // Create the 'reflog' table
+    conn.execute(
+        "CREATE TABLE reflog (
+                        repo         TEXT NOT NULL,
+                        ref_name     TEXT NOT NULL,
+                        reflog_index INTEGER NOT NULL,
+                        old_id       TEXT NOT NULL,
+                        old_full_id  TEXT NOT NULL,
+                        new_id       TEXT NOT NULL,
+                        new_full_id  TEXT NOT NULL,
+                        committer    TEXT,
+                        date         TEXT,
+                        message      TEXT,
+                        PRIMARY KEY (repo, ref_name, reflog_index)
+                    )",
+        (),
+    )?;
 
-    // Iterate over the query results and add rows to the table
-    while let Some(row) = rows.next()? {
-        let values: Vec<String> = (0..column_len)
-            .map(|col_idx| {
-                let value: Value = row.get(col_idx).unwrap_or(Value::Null);
-                value_to_string(value)
-            })
-            .collect();
+    Ok(())
+}
 
-        table.add_row(values);
-        row_count += 1;
-    }
+// Function to populate the tables with the Git data of a repo
+fn load_repo(
+    conn: &Connection,
+    repo: &mut Repository,
+    args: &Args,
+    ctx: &LoadContext,
+    loaded_tips: &mut HashSet<Oid>,
+) -> Result<(), Error> {
+    // Insert the history of HEAD, unless skipped. A new repo has no commits yet, so HEAD is unborn
+    if !args.no_commits {
+        match traverse(
+            conn,
+            repo,
+            None,
+            &TraverseOptions {
+                topo_order: args.topo_order,
+                date_order: args.date_order,
+                reverse: args.reverse,
+                ..TraverseOptions::default()
+            },
+            args.files,
+            ctx,
+            loaded_tips,
+        ) {
+            Err(Error::GitError(err)) if err.code() == ErrorCode::UnbornBranch => {}
+            result => result?,
+        }
+    }
+
+    // Insert tags, unless skipped
+    if !args.no_tags {
+        let mut tag_sql_error: Option<Error> = None;
+
+        repo.tag_foreach(|id, name| {
+            // Use the name of the ref, without the "refs/tags/" prefix, for both types of tags
+            let name = String::from_utf8_lossy(name);
+            let name = name.strip_prefix("refs/tags/").unwrap_or(&name).to_string();
+
+            match repo.find_tag(id) {
+                // Annotated tag
+                Ok(t) => {
+                    if let Err(err) = insert_tag(conn, GitTag::Annotated(t, name), ctx) {
+                        tag_sql_error = Some(err);
+                        return false; // Stop iterating over tags
+                    }
+                }
+                // Lightweight tag
+                _ => {
+                    // The tag refers to its target directly. Peel the target to a commit where
+                    // possible, so the tag can be joined with the commits table
+                    let (target_id, target_type, target_time) = match repo.find_object(id, None) {
+                        Ok(object) => match object.peel(ObjectType::Commit) {
+                            Ok(commit) => (
+                                commit.id(),
+                                Some(ObjectType::Commit),
+                                commit.as_commit().map(|c| c.author().when()),
+                            ),
+                            Err(_) => (object.id(), object.kind(), None),
+                        },
+                        Err(err) => {
+                            tag_sql_error = Some(err.into());
+                            return false; // Stop iterating over tags
+                        }
+                    };
+
+                    if let Err(err) = insert_tag(
+                        conn,
+                        GitTag::Lightweight {
+                            id,
+                            name,
+                            target_id,
+                            target_type,
+                            target_time,
+                        },
+                        ctx,
+                    ) {
+                        tag_sql_error = Some(err);
+                        return false; // Stop iterating over tags
+                    }
+                }
+            };
+
+            // Continue iterating over tags
+            true
+        })?;
+
+        if let Some(tag_sql_err) = tag_sql_error {
+            return Err(tag_sql_err);
+        }
+    }
+
+    // Insert branches, unless skipped
+    if !args.no_branches {
+        for branch in repo.branches(None)? {
+            let result = branch
+                .map_err(Error::from)
+                .and_then(|(b, b_type)| insert_branch(conn, repo, b, Some(b_type), ctx));
+
+            // Skip a branch that can't be read (e.g., a corrupt ref), rather than failing the whole load
+            match result {
+                Err(Error::GitError(err)) => eprintln!("Skipping unreadable branch. {}", err),
+                result => result?,
+            }
+        }
+
+        // When HEAD is detached, no branch is checked out, so insert HEAD itself
+        if repo.head_detached()? {
+            insert_branch(conn, repo, Branch::wrap(repo.head()?), None, ctx)?;
+        }
+    }
+
+    // Insert remotes
+    for name in repo.remotes()?.iter().flatten() {
+        insert_remote(conn, &repo.find_remote(name)?, ctx)?;
+    }
+
+    // Insert stashes. They are collected first, as iterating over them borrows the repo mutably
+    let mut stashes = Vec::new();
+    repo.stash_foreach(|index, message, id| {
+        stashes.push((index, message.to_string(), *id));
+        true // Continue iterating over stashes
+    })?;
+
+    for (index, message, id) in stashes {
+        insert_stash(conn, index, &message, id, ctx)?;
+    }
+
+    // Insert the reflog entries, from the most recent. A ref without a reflog has none
+    for (index, entry) in repo.reflog(&args.reflog)?.iter().enumerate() {
+        insert_reflog_entry(conn, &args.reflog, index, &entry, ctx)?;
+    }
+
+    // Insert submodules. They can't be listed without a working tree, so a bare repo has none
+    if !repo.is_bare() {
+        for submodule in repo.submodules()? {
+            insert_submodule(conn, &submodule, ctx)?;
+        }
+    }
+
+    insert_meta(conn, repo, ctx)?;
+
+    Ok(())
+}
+
+// Function to create the tables and populate them with the Git data of each repo
+fn populate_db(
+    conn: &Connection,
+    repos: &mut [Repository],
+    args: &Args,
+    loaded_tips: &mut HashSet<Oid>,
+) -> Result<(), Error> {
+    create_tables(conn)?;
+
+    let mut names: Vec<String> = Vec::new();
+
+    for repo in repos.iter_mut() {
+        // Fall back to the full path when two repos have the same directory name
+        let mut name = repo_name(repo);
+        if names.contains(&name) {
+            name = repo
+                .workdir()
+                .unwrap_or(repo.path())
+                .to_string_lossy()
+                .to_string();
+        }
+
+        let ctx = LoadContext {
+            repo_name: &name,
+            abbrev_len: usize::from(args.abbrev),
+            show_progress: show_progress(args),
+        };
+
+        // Later commands only traverse the first repo, so only its loaded commits are tracked
+        let mut other_tips = HashSet::new();
+        let tips = if names.is_empty() {
+            &mut *loaded_tips
+        } else {
+            &mut other_tips
+        };
+
+        load_repo(conn, repo, args, &ctx, tips)?;
+        names.push(name);
+    }
+
+    // Record how the database was loaded, so a later run can tell whether to reuse it
+    conn.execute(
+        &format!(
+            "INSERT INTO {} (key, value) VALUES ('load_options', ?1)",
+            INTERNAL_TABLE
+        ),
+        [load_options(repos, args)],
+    )?;
+
+    // Create indexes on frequently queried columns. These are created after the
+    // bulk insert, as building them once is faster than updating them on every insert.
+    // Dates are indexed by epoch, as text with different offsets doesn't sort in time order
+    conn.execute_batch(
+        "CREATE INDEX commits_date_epoch_idx ON commits (date_epoch);
+         CREATE INDEX commits_author_idx ON commits (author);
+         CREATE INDEX tags_target_full_id_idx ON tags (target_full_id);",
+    )?;
+
+    // Create the 'commits_fts' full-text index of commit messages, if requested. It reads
+    // the text from the commits table, and is built after the bulk insert, like the indexes.
+    // Triggers keep it in sync with commits inserted later by `traverse`, and with commits
+    // deleted or updated by queries, which would otherwise leave it out of sync
+    if args.fts {
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE commits_fts USING fts5 (
+                 id, subject, body, content = 'commits', content_rowid = 'rowid'
+             );
+             INSERT INTO commits_fts (commits_fts) VALUES ('rebuild');
+             CREATE TRIGGER commits_fts_insert AFTER INSERT ON commits BEGIN
+                 INSERT INTO commits_fts (rowid, id, subject, body)
+                 VALUES (new.rowid, new.id, new.subject, new.body);
+             END;
+             CREATE TRIGGER commits_fts_delete AFTER DELETE ON commits BEGIN
+                 INSERT INTO commits_fts (commits_fts, rowid, id, subject, body)
+                 VALUES ('delete', old.rowid, old.id, old.subject, old.body);
+             END;
+             CREATE TRIGGER commits_fts_update AFTER UPDATE ON commits BEGIN
+                 INSERT INTO commits_fts (commits_fts, rowid, id, subject, body)
+                 VALUES ('delete', old.rowid, old.id, old.subject, old.body);
+                 INSERT INTO commits_fts (rowid, id, subject, body)
+                 VALUES (new.rowid, new.id, new.subject, new.body);
+             END;",
+        )?;
+    }
+
+    Ok(())
+}
+
+// Function to register custom SQL functions
+fn register_functions(conn: &Connection) -> Result<(), Error> {
+    // `age(date)` returns the number of whole days since the given datetime
+    conn.create_scalar_function("age", 1, FunctionFlags::SQLITE_UTF8, |ctx| {
+        let date: Option<String> = ctx.get(0)?;
+
+        Ok(date
+            .and_then(|d| DateTime::parse_from_rfc3339(&d).ok())
+            .map(|d| Utc::now().signed_duration_since(d).num_days()))
+    })?;
+
+    // `regexp(pattern, text)` backs the REGEXP operator (`text REGEXP pattern`).
+    // Compiled patterns are cached, as the function is called for every row
+    let mut regexes: HashMap<String, Regex> = HashMap::new();
+    conn.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            let pattern: Option<String> = ctx.get(0)?;
+            let text: Option<String> = ctx.get(1)?;
+
+            // Like other SQL operators, a NULL pattern or text gives NULL
+            let (Some(pattern), Some(text)) = (pattern, text) else {
+                return Ok(None);
+            };
+
+            let regex = match regexes.entry(pattern) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let regex = Regex::new(entry.key())
+                        .map_err(|err| rusqlite::Error::UserFunctionError(Box::new(err)))?;
+                    entry.insert(regex)
+                }
+            };
+
+            Ok(Some(regex.is_match(&text)))
+        },
+    )?;
+
+    // `week(date)` and `month(date)` bucket a date into its ISO week (`2024-W03`) or month
+    // (`2024-03`), in the date's own timezone, for histograms with GROUP BY
+    conn.create_scalar_function(
+        "week",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let date: Option<String> = ctx.get(0)?;
+
+            Ok(date
+                .and_then(|d| DateTime::parse_from_rfc3339(&d).ok())
+                .map(|d| {
+                    let week = d.iso_week();
+                    format!("{}-W{:02}", week.year(), week.week())
+                }))
+        },
+    )?;
+    conn.create_scalar_function(
+        "month",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let date: Option<String> = ctx.get(0)?;
+
+            Ok(date
+                .and_then(|d| DateTime::parse_from_rfc3339(&d).ok())
+                .map(|d| d.format("%Y-%m").to_string()))
+        },
+    )?;
+
+    // `domain(email)` returns the part of an email after the `@`, in lowercase, as domains
+    // are case-insensitive. NULL if there is no `@`
+    conn.create_scalar_function(
+        "domain",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let email: Option<String> = ctx.get(0)?;
+
+            Ok(email
+                .as_deref()
+                .and_then(|e| e.rsplit_once('@'))
+                .map(|(_, domain)| domain.to_lowercase()))
+        },
+    )?;
+
+    Ok(())
+}
+
+// Function to initialize the SQLite database with Git commit data
+fn init_db(
+    repos: &mut [Repository],
+    args: &Args,
+    loaded_tips: &mut HashSet<Oid>,
+) -> Result<Connection, Error> {
+    // Open a file-backed SQLite database if a path is given, otherwise an in-memory one
+    let conn = match &args.db {
+        Some(path) => Connection::open(path)?,
+        None => Connection::open_in_memory()?,
+    };
+
+    register_functions(&conn)?;
+
+    // Reuse a previously populated database, unless a rebuild is requested. A database
+    // loaded from other repos or with other options is rebuilt, rather than serving stale data
+    if !args.refresh {
+        match stored_load_options(&conn)? {
+            Some(options) if options == load_options(repos, args) => return Ok(conn),
+            Some(_) if !args.quiet => eprintln!(
+                "Rebuilding the database, as it was loaded from other repos or with other options"
+            ),
+            _ => {}
+        }
+    }
+
+    // Populate the database in a single transaction, which is much faster than
+    // committing each insert, and leaves the database unchanged if loading fails
+    in_transaction(&conn, |tx| populate_db(tx, repos, args, loaded_tips))?;
+
+    Ok(conn)
+}
+
+// Function to convert bytes to hexadecimal digits
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Function to convert SQLite Value to a String
+fn value_to_string(value: Value, settings: &Settings) -> String {
+    match value {
+        Value::Integer(i) => i.to_string(),
+        Value::Real(f) => f.to_string(),
+        // Replace \r\n with \n, as \r\n causes formatting issues with table
+        Value::Text(s) => s.replace("\r\n", "\n"),
+        Value::Blob(b) => match settings.blob_format {
+            BlobFormat::Hex => to_hex(&b),
+            BlobFormat::Length => format!("{} bytes", b.len()),
+        },
+        Value::Null => settings
+            .nullvalue
+            .clone()
+            .unwrap_or_else(|| String::from("NULL")),
+    }
+}
+
+// Function to convert SQLite Value to a String for display, truncated to the
+// maximum column width, if any
+fn value_to_cell(value: Value, settings: &Settings) -> String {
+    let text = value_to_string(value, settings);
+
+    match settings.max_col_width.map(usize::from) {
+        Some(max) if text.chars().count() > max => {
+            // The ellipsis takes the place of the last character
+            let mut cell: String = text.chars().take(max - 1).collect();
+            cell.push('…');
+            cell
+        }
+        _ => text,
+    }
+}
+
+// Function to pick the color of a value in a table: SHAs like `git log`, dates, and NULLs
+// stand out from the other values
+fn value_color(value: &Value) -> Option<Color> {
+    match value {
+        Value::Null => Some(Color::DarkGrey),
+        Value::Text(s) if is_sha(s) => Some(Color::Yellow),
+        Value::Text(s) if DateTime::parse_from_rfc3339(s).is_ok() => Some(Color::Cyan),
+        _ => None,
+    }
+}
+
+// Function to check whether text looks like an abbreviated or full object ID. Shorter
+// text than git's default abbreviation is more likely to be a word, like `added`
+fn is_sha(text: &str) -> bool {
+    (7..=40).contains(&text.len()) && text.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+// Function to convert SQLite Value to a JSON value
+fn value_to_json(value: Value, settings: &Settings) -> serde_json::Value {
+    match value {
+        Value::Integer(i) => serde_json::Value::from(i),
+        // Non-finite floats have no JSON representation, so become null
+        Value::Real(f) => serde_json::Value::from(f),
+        Value::Text(s) => serde_json::Value::from(s),
+        Value::Blob(_) => serde_json::Value::from(value_to_string(value, settings)),
+        Value::Null => serde_json::Value::Null,
+    }
+}
+
+// Function to quote a CSV field, if needed, per RFC 4180
+fn csv_field(field: &str) -> String {
+    if field.contains(['"', ',', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Function to convert SQLite Value to a CSV field
+fn value_to_csv(value: Value, settings: &Settings) -> String {
+    match value {
+        // Keep the original line endings, as quoted fields can contain them
+        Value::Text(s) => csv_field(&s),
+        // NULL becomes an empty field, unless other text is given for it
+        Value::Null => csv_field(settings.nullvalue.as_deref().unwrap_or_default()),
+        _ => csv_field(&value_to_string(value, settings)),
+    }
+}
+
+// Function to escape a cell of a Markdown table
+fn markdown_cell(cell: &str) -> String {
+    // Newlines would end the table row, so become HTML line breaks
+    cell.replace('|', "\\|").replace('\n', "<br>")
+}
+
+// Function to display query results in a table
+fn print_table(
+    out: &mut impl Write,
+    column_names: &[String],
+    rows: Vec<Vec<Value>>,
+    sql: &str,
+    settings: &Settings,
+) -> std::io::Result<()> {
+    let row_count = rows.len();
+
+    // Create a comfy_table for displaying query results
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(column_names);
+
+    // Without a width, the table fits the terminal width
+    if let Some(w) = settings.width {
+        table.set_width(w);
+    }
+
+    // Add the query results to the table, up to the maximum number of rows
+    let max_rows = match settings.max_rows {
+        0 => row_count,
+        max => max,
+    };
+    // A file gets plain text, as it's not viewed in a terminal
+    let color = settings.color && settings.output.is_none();
+    if color {
+        // comfy_table only styles cells when stdout is a terminal, not when paging
+        table.enforce_styling();
+    }
+    for row in rows.into_iter().take(max_rows) {
+        table.add_row(row.into_iter().map(|value| {
+            let color = color.then(|| value_color(&value)).flatten();
+            let cell = Cell::new(value_to_cell(value, settings));
+
+            match color {
+                Some(color) => cell.fg(color),
+                None => cell,
+            }
+        }));
+    }
+
+    // Print the table and the row count
+    writeln!(out, "{table}")?;
+    if row_count > max_rows {
+        writeln!(out, "... (showing {} of {} rows)", max_rows, row_count)?;
+    }
+    if settings.quiet {
+        return Ok(());
+    }
+
+    writeln!(out, "Rows returned: {}", row_count)?;
+
+    // Show tip if no results returned and SQL query contains `commits`
+    if row_count == 0 && sql.contains("commits") {
+        writeln!(
+            out,
+            "Tip: use the `traverse <rev>` command to insert commit history"
+        )?;
+    }
+
+    Ok(())
+}
+
+// Function to display query results as a JSON array of objects keyed by column name
+fn print_json(
+    out: &mut impl Write,
+    column_names: &[String],
+    rows: Vec<Vec<Value>>,
+    settings: &Settings,
+) -> std::io::Result<()> {
+    let objects: Vec<serde_json::Value> = rows
+        .into_iter()
+        .map(|row| {
+            column_names
+                .iter()
+                .cloned()
+                .zip(row.into_iter().map(|value| value_to_json(value, settings)))
+                .collect()
+        })
+        .collect();
+
+    // The alternate format flag pretty-prints the JSON
+    writeln!(out, "{:#}", serde_json::Value::Array(objects))?;
+
+    Ok(())
+}
+
+// Function to display query results as CSV, with a header row of column names
+fn print_csv(
+    out: &mut impl Write,
+    column_names: &[String],
+    rows: Vec<Vec<Value>>,
+    settings: &Settings,
+) -> std::io::Result<()> {
+    // RFC 4180 uses CRLF to end each record
+    let header: Vec<String> = column_names.iter().map(|name| csv_field(name)).collect();
+    write!(out, "{}\r\n", header.join(","))?;
+
+    for row in rows {
+        let fields: Vec<String> = row
+            .into_iter()
+            .map(|value| value_to_csv(value, settings))
+            .collect();
+        write!(out, "{}\r\n", fields.join(","))?;
+    }
+
+    Ok(())
+}
+
+// Function to display query results as a GitHub-flavored Markdown table
+fn print_markdown(
+    out: &mut impl Write,
+    column_names: &[String],
+    rows: Vec<Vec<Value>>,
+    settings: &Settings,
+) -> std::io::Result<()> {
+    let header: Vec<String> = column_names
+        .iter()
+        .map(|name| markdown_cell(name))
+        .collect();
+    writeln!(out, "| {} |", header.join(" | "))?;
+    writeln!(out, "|{}", " --- |".repeat(column_names.len()))?;
+
+    for row in rows {
+        let cells: Vec<String> = row
+            .into_iter()
+            .map(|value| markdown_cell(&value_to_cell(value, settings)))
+            .collect();
+        writeln!(out, "| {} |", cells.join(" | "))?;
+    }
+
+    Ok(())
+}
+
+// Function to display each row of query results as `column = value` lines, like sqlite's `.mode line`
+fn print_lines(
+    out: &mut impl Write,
+    column_names: &[String],
+    rows: Vec<Vec<Value>>,
+    settings: &Settings,
+) -> std::io::Result<()> {
+    // Right-align the column names, so the values line up
+    let name_width = column_names
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    for (i, row) in rows.into_iter().enumerate() {
+        // Separate rows with a blank line
+        if i > 0 {
+            writeln!(out)?;
+        }
+
+        for (name, value) in column_names.iter().zip(row) {
+            writeln!(
+                out,
+                "{:>name_width$} = {}",
+                name,
+                value_to_cell(value, settings)
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// Function to write query results in the given format
+fn print_results(
+    out: &mut impl Write,
+    column_names: &[String],
+    rows: Vec<Vec<Value>>,
+    sql: &str,
+    settings: &Settings,
+) -> std::io::Result<()> {
+    match settings.format {
+        Format::Table => print_table(out, column_names, rows, sql, settings),
+        Format::Json => print_json(out, column_names, rows, settings),
+        Format::Csv => print_csv(out, column_names, rows, settings),
+        Format::Markdown => print_markdown(out, column_names, rows, settings),
+        Format::Line => print_lines(out, column_names, rows, settings),
+    }
+}
+
+// Function to display output, through the pager command if one is given
+fn display_output(output: &[u8], pager: Option<&str>) -> std::io::Result<()> {
+    let mut pager_args = pager.into_iter().flat_map(str::split_whitespace);
+
+    if let Some(program) = pager_args.next() {
+        // If the pager can't be started, fall back to printing directly
+        if let Ok(mut child) = Command::new(program)
+            .args(pager_args)
+            .stdin(Stdio::piped())
+            .spawn()
+        {
+            let result = child
+                .stdin
+                .take()
+                .map_or(Ok(()), |mut stdin| stdin.write_all(output));
+            child.wait()?;
+
+            return result;
+        }
+    }
+
+    let mut stdout = stdout().lock();
+    stdout.write_all(output)?;
+    stdout.flush()
+}
+
+// Function to run an SQL query and display the results in the given format
+fn run_sql_query(conn: &Connection, sql: &str, settings: &Settings) -> Result<(), Error> {
+    let start = Instant::now();
+    let mut stmt = conn.prepare(sql)?;
+    let column_names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let column_len = column_names.len();
+
+    // Execute the SQL query
+    let mut rows = stmt.query([])?;
+    let mut results = Vec::new();
+
+    // Iterate over the query results and collect the values of each row
+    while let Some(row) = rows.next()? {
+        let values: Vec<Value> = (0..column_len)
+            .map(|col_idx| row.get(col_idx).unwrap_or(Value::Null))
+            .collect();
+
+        results.push(values);
+    }
+
+    // The query runs as its rows are fetched, so time up to here, excluding the display
+    let elapsed = start.elapsed();
+
+    // Format the whole output first, so it can be sent to the pager in one go
+    let mut output = Vec::new();
+    let result = print_results(&mut output, &column_names, results, sql, settings).and_then(|()| {
+        if settings.timer {
+            writeln!(output, "Run Time: {:.3}s", elapsed.as_secs_f64())?;
+        }
+
+        match settings.output.as_ref() {
+            Some(mut file) => file.write_all(&output),
+            None => display_output(&output, settings.pager.as_deref()),
+        }
+    });
+
+    // A closed pipe (e.g., the pager was quit early) isn't an error
+    if let Err(err) = result {
+        if err.kind() != ErrorKind::BrokenPipe {
+            eprintln!("Failed to display results. {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+// Function to print the names of the tables in the database
+fn print_tables(conn: &Connection) -> Result<(), Error> {
+    // The shadow tables of a full-text index (e.g., `commits_fts_data`) are left out,
+    // as they're internal to the virtual table, and so is git-query's internal table
+    let mut stmt = conn.prepare(
+        "SELECT name FROM sqlite_master m WHERE type = 'table'
+         AND NOT EXISTS (
+             SELECT 1 FROM sqlite_master v
+             WHERE v.sql LIKE 'CREATE VIRTUAL TABLE%' AND m.name LIKE v.name || '\\_%' ESCAPE '\\'
+         )
+         AND name != ?1
+         ORDER BY name",
+    )?;
+    let names = stmt.query_map([INTERNAL_TABLE], |row| row.get::<_, String>(0))?;
+
+    for name in names {
+        println!("{}", name?);
+    }
+
+    Ok(())
+}
+
+// Function to print the CREATE TABLE statements of all tables, or of the given table
+fn print_schema(conn: &Connection, table: Option<&str>) -> Result<(), Error> {
+    let mut stmt = conn.prepare(
+        "SELECT sql FROM sqlite_master WHERE type = 'table' AND (?1 IS NULL OR name = ?1) ORDER BY name",
+    )?;
+    let statements = stmt
+        .query_map([table], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+
+    if let (Some(t), true) = (table, statements.is_empty()) {
+        eprintln!("No such table: {}", t);
+    }
+
+    for sql in statements {
+        println!("{};", sql);
+    }
+
+    Ok(())
+}
+
+// Function to find an escape sequence that doesn't occur in text, like sqlite3's
+// `unused_string`: the preferred one, the alternative, or the alternative with a number
+fn unused_string(text: &str, preferred: &str, alternative: &str) -> String {
+    if !text.contains(preferred) {
+        return preferred.to_string();
+    }
+    if !text.contains(alternative) {
+        return alternative.to_string();
+    }
+
+    (0..)
+        .map(|i| format!("({}{})", alternative, i))
+        .find(|escape| !text.contains(escape.as_str()))
+        .unwrap_or_default()
+}
+
+// Function to convert SQLite Value to an SQL literal
+fn value_to_sql(value: Value) -> String {
+    match value {
+        Value::Integer(i) => i.to_string(),
+        // The debug format keeps the decimal point, so the value stays a float
+        Value::Real(f) => format!("{:?}", f),
+        Value::Text(s) => {
+            let literal = format!("'{}'", s.replace('\'', "''"));
+
+            // Like sqlite3's `.dump`, line breaks are written as escapes restored with
+            // char(), so each statement stays on a single line. The escapes are ones that
+            // don't occur in the text, so none of the text itself is replaced
+            if s.contains(['\r', '\n']) {
+                let cr = unused_string(&s, "\\r", "\\015");
+                let lf = unused_string(&s, "\\n", "\\012");
+
+                format!(
+                    "replace(replace({},'{}',char(13)),'{}',char(10))",
+                    literal.replace('\r', &cr).replace('\n', &lf),
+                    cr,
+                    lf
+                )
+            } else {
+                literal
+            }
+        }
+        Value::Blob(b) => format!("X'{}'", to_hex(&b)),
+        Value::Null => String::from("NULL"),
+    }
+}
+
+// Function to rebuild the database to reclaim the space of deleted data, e.g. after
+// on-demand loads are replaced, and print the number of pages before and after
+fn vacuum(conn: &Connection) -> Result<(), Error> {
+    let page_count = || conn.query_row("PRAGMA page_count", (), |row| row.get::<_, i64>(0));
+
+    let before = page_count()?;
+    conn.execute("VACUUM", ())?;
+    let after = page_count()?;
+
+    // VACUUM can renumber the rowids of commits, which the full-text index refers to
+    let has_fts: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE name = 'commits_fts')",
+        (),
+        |row| row.get(0),
+    )?;
+    if has_fts {
+        conn.execute(
+            "INSERT INTO commits_fts (commits_fts) VALUES ('rebuild')",
+            (),
+        )?;
+    }
+
+    println!("Pages: {} before, {} after", before, after);
+
+    Ok(())
+}
+
+// Function to save a query as an SQLite view, replacing any view with the same name
+fn save_view(conn: &Connection, name: &str, sql: &str) -> Result<(), Error> {
+    // A query is usually entered with the `;` that ends statements
+    let sql = sql.trim_end_matches(|c: char| c == ';' || c.is_whitespace());
+
+    conn.execute(
+        &format!("DROP VIEW IF EXISTS {}", quote_identifier(name)),
+        (),
+    )?;
+    conn.execute(
+        &format!("CREATE VIEW {} AS {}", quote_identifier(name), sql),
+        (),
+    )?;
+
+    Ok(())
+}
+
+// Function to quote an SQL identifier, e.g. a table name
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// Function to export the schema and data of the database as SQL statements, like sqlite3's `.dump`
+fn dump_sql(conn: &Connection) -> Result<String, Error> {
+    let mut dump = String::from("BEGIN TRANSACTION;\n");
+
+    let tables: Vec<(String, String)> = conn
+        .prepare(
+            "SELECT name, sql FROM sqlite_master WHERE type = 'table' AND sql NOT NULL ORDER BY rowid",
+        )?
+        .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+
+    // Full-text index tables store their data in shadow tables (e.g., `commits_fts_data`),
+    // which are created with the virtual table, so only the virtual table is dumped
+    let virtual_tables: Vec<&String> = tables
+        .iter()
+        .filter(|(_, sql)| sql.starts_with("CREATE VIRTUAL TABLE"))
+        .map(|(name, _)| name)
+        .collect();
+
+    for (name, sql) in &tables {
+        if virtual_tables
+            .iter()
+            .any(|v| name.starts_with(&format!("{}_", v)))
+        {
+            continue;
+        }
+
+        dump.push_str(&format!("{};\n", sql));
+
+        // The index of a virtual table is rebuilt from its content table, rather than dumped
+        if virtual_tables.contains(&name) {
+            dump.push_str(&format!(
+                "INSERT INTO {0} ({0}) VALUES ('rebuild');\n",
+                quote_identifier(name)
+            ));
+            continue;
+        }
+
+        let mut stmt = conn.prepare(&format!("SELECT * FROM {}", quote_identifier(name)))?;
+        let column_len = stmt.column_count();
+        let mut rows = stmt.query([])?;
+
+        while let Some(row) = rows.next()? {
+            let values: Vec<String> = (0..column_len)
+                .map(|col_idx| value_to_sql(row.get(col_idx).unwrap_or(Value::Null)))
+                .collect();
+
+            dump.push_str(&format!(
+                "INSERT INTO {} VALUES({});\n",
+                quote_identifier(name),
+                values.join(",")
+            ));
+        }
+    }
+
+    // Create the indexes, triggers and views after the data is inserted
+    let mut stmt = conn.prepare(
+        "SELECT sql FROM sqlite_master WHERE type IN ('index', 'trigger', 'view') AND sql NOT NULL ORDER BY rowid",
+    )?;
+    for sql in stmt.query_map((), |row| row.get::<_, String>(0))? {
+        dump.push_str(&format!("{};\n", sql?));
+    }
+
+    dump.push_str("COMMIT;\n");
+
+    Ok(dump)
+}
+
+// Function to resolve a revision (e.g., a branch name, tag name or commit id) to a commit
+fn resolve_commit<'a>(repo: &'a Repository, rev: &str) -> Result<GitCommit<'a>, Error> {
+    match repo.revparse_single(rev) {
+        Ok(object) => object.peel_to_commit().map_err(|_| {
+            Error::GitError(git2::Error::from_str(&format!(
+                "`{}` does not refer to a commit",
+                rev
+            )))
+        }),
+        // Fall back to looking up the revision as a commit id prefix, reporting the
+        // original error if that fails too, as it's more descriptive
+        Err(err) => repo.find_commit_by_prefix(rev).map_err(|_| {
+            Error::GitError(git2::Error::from_str(&format!(
+                "`{}` is not a valid revision: {}",
+                rev,
+                err.message()
+            )))
+        }),
+    }
+}
+
+// Function to insert a commit, and optionally the files it changed, into the database
+fn load_commit(
+    conn: &Connection,
+    repo: &Repository,
+    commit: &GitCommit,
+    load_files: bool,
+    ctx: &LoadContext,
+) -> Result<(), Error> {
+    let diff = diff_commit(repo, commit, None)?;
+
+    insert_commit(conn, repo, commit, &diff, ctx)?;
+
+    // Inserting a row per file is comparatively slow, so files are only loaded when requested
+    if load_files {
+        insert_commit_files(conn, commit, &diff, ctx)?;
+    }
+
+    Ok(())
+}
+
+// Function to find the best common ancestor of two revisions, like `git merge-base`,
+// and insert it into the database if it isn't there already. Returns its full id
+fn merge_base(
+    conn: &Connection,
+    repo: &Repository,
+    rev_a: &str,
+    rev_b: &str,
+    load_files: bool,
+    ctx: &LoadContext,
+) -> Result<Oid, Error> {
+    let id_a = resolve_commit(repo, rev_a)?.id();
+    let id_b = resolve_commit(repo, rev_b)?.id();
+    let base = repo.find_commit(repo.merge_base(id_a, id_b)?)?;
+
+    load_commit(conn, repo, &base, load_files, ctx)?;
+
+    Ok(base.id())
+}
+
+// Options for the `traverse` command
+#[derive(Default)]
+struct TraverseOptions {
+    // Only commits made at or after this time (in seconds since the epoch) are inserted
+    since: Option<i64>,
+    // Only commits made at or before this time (in seconds since the epoch) are inserted
+    until: Option<i64>,
+    // Whether only the first parent of each merge is followed, like `git log --first-parent`
+    first_parent: bool,
+    // Whether the histories of all local and remote branches are traversed, like `git log --all`
+    all: bool,
+    // Orders in which to walk the history, which is also the order commits are inserted in
+    topo_order: bool,
+    date_order: bool,
+    reverse: bool,
+}
+
+// Function to parse a date given to `--since` or `--until`, as seconds since the epoch.
+// A date without a time (YYYY-MM-DD) is in UTC, and covers the whole day when `end_of_day` is set
+fn parse_traverse_date(date: &str, end_of_day: bool) -> Result<i64, String> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(date) {
+        return Ok(datetime.timestamp());
+    }
+
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+        format!(
+            "Invalid date `{}`. Use YYYY-MM-DD or an ISO 8601 datetime, e.g. 2024-02-13T04:49:34+01:00",
+            date
+        )
+    })?;
+    let time = if end_of_day {
+        day.and_hms_opt(23, 59, 59)
+    } else {
+        day.and_hms_opt(0, 0, 0)
+    };
+
+    Ok(time.map_or(0, |t| t.and_utc().timestamp()))
+}
+
+// Function to parse the arguments of the `traverse` command into the revision and options
+// The revision is None with `--all`, which traverses every branch instead
+fn parse_traverse_args<'a>(
+    words: &[&'a str],
+) -> Result<(Option<&'a str>, TraverseOptions), String> {
+    let mut rev = None;
+    let mut options = TraverseOptions::default();
+    let mut words = words.iter();
+
+    while let Some(&word) = words.next() {
+        match word {
+            "--since" | "--until" => {
+                let date = words
+                    .next()
+                    .ok_or_else(|| format!("`{}` needs a date", word))?;
+
+                if word == "--since" {
+                    options.since = Some(parse_traverse_date(date, false)?);
+                } else {
+                    options.until = Some(parse_traverse_date(date, true)?);
+                }
+            }
+            "--first-parent" => options.first_parent = true,
+            "--topo-order" => options.topo_order = true,
+            "--date-order" => options.date_order = true,
+            "--reverse" => options.reverse = true,
+            "--all" => options.all = true,
+            _ if word.starts_with("--") => return Err(format!("Unknown option `{}`", word)),
+            _ if rev.is_some() => return Err(String::from("Only one revision can be traversed")),
+            _ => rev = Some(word),
+        }
+    }
+
+    match (rev, options.all) {
+        (None, false) => Err(String::from("A revision to traverse is needed")),
+        (Some(_), true) => Err(String::from(
+            "`--all` traverses every branch, so no revision is needed",
+        )),
+        _ => Ok((rev, options)),
+    }
+}
+
+// Function to traverse commit history and insert into database.
+// `loaded_tips` holds commits whose entire history was already inserted by a previous
+// traversal. These commits and their ancestors are skipped, so re-traversal is cheap
+fn traverse(
+    conn: &Connection,
+    repo: &Repository,
+    rev: Option<&str>,
+    options: &TraverseOptions,
+    load_files: bool,
+    ctx: &LoadContext,
+    loaded_tips: &mut HashSet<Oid>,
+) -> Result<(), Error> {
+    // Create a revwalk to traverse the commit history
+    let mut revwalk = repo.revwalk()?;
+
+    // The commits whose entire history is inserted by this traversal, if any
+    let tips = if options.all {
+        // Start from the tip of every local and remote branch
+        let mut tips = Vec::new();
+        for glob in ["refs/heads/*", "refs/remotes/*"] {
+            for reference in repo.references_glob(glob)? {
+                // Skip refs that don't point to a commit, e.g. a symbolic `origin/HEAD`
+                // to a missing branch
+                if let Ok(commit) = reference?.peel_to_commit() {
+                    revwalk.push(commit.id())?;
+                    tips.push(commit.id());
+                }
+            }
+        }
+
+        tips
+    } else if let Some((from, to)) = rev.and_then(|r| r.split_once("..")) {
+        // Like `git log A..B`, a range includes commits reachable from B but not from A.
+        // Either side of the range defaults to HEAD
+        let from = if from.is_empty() { "HEAD" } else { from };
+        let to = if to.is_empty() { "HEAD" } else { to };
+
+        revwalk.hide(resolve_commit(repo, from)?.id())?;
+        revwalk.push(resolve_commit(repo, to)?.id())?;
+
+        // The history of B before A is not inserted
+        Vec::new()
+    } else {
+        let commit = match rev {
+            Some(r) => resolve_commit(repo, r)?,
+            None => repo.head()?.peel_to_commit()?,
+        };
+        revwalk.push(commit.id())?;
+
+        vec![commit.id()]
+    };
+
+    for loaded_tip in loaded_tips.iter() {
+        revwalk.hide(*loaded_tip)?;
+    }
+
+    if options.first_parent {
+        revwalk.simplify_first_parent()?;
+    }
+
+    let mut sorting = Sort::NONE;
+    if options.topo_order {
+        sorting |= Sort::TOPOLOGICAL;
+    }
+    if options.date_order {
+        sorting |= Sort::TIME;
+    }
+    if options.reverse {
+        sorting |= Sort::REVERSE;
+    }
+    revwalk.set_sorting(sorting)?;
+
+    // Iterate over Git commit history and insert each commit into the database
+    for (count, commit_id) in revwalk.enumerate() {
+        report_progress(ctx, "Loading commits", count);
+
+        let commit_id = commit_id?;
+        let commit = repo.find_commit(commit_id)?;
+
+        // Skip commits outside the date range. Commit times aren't ordered along the
+        // history (e.g., after a rebase), so the rest of the history is still walked
+        let time = commit.time().seconds();
+        if options.since.is_some_and(|since| time < since)
+            || options.until.is_some_and(|until| time > until)
+        {
+            continue;
+        }
+
+        load_commit(conn, repo, &commit, load_files, ctx)?;
+    }
+
+    clear_progress(ctx);
+
+    // Skipped commits aren't inserted, so the tip's history is only complete without a date
+    // range, and when all parents were followed
+    let is_complete = options.since.is_none() && options.until.is_none() && !options.first_parent;
+    if is_complete {
+        loaded_tips.extend(tips);
+    }
+
+    Ok(())
+}
+
+// Function to insert the files in the tree of a commit into the `blobs` table.
+// Reading every blob is slow for large trees, so this is only done on request
+fn load_tree(
+    conn: &Connection,
+    repo: &Repository,
+    rev: &str,
+    ctx: &LoadContext,
+) -> Result<(), Error> {
+    let commit = resolve_commit(repo, rev)?;
+    let commit_id = abbrev(commit.id(), ctx.abbrev_len);
+
+    // Collect the paths and ids of the blobs first, as errors can't be returned from the walk
+    let mut entries = Vec::new();
+    commit.tree()?.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        // Submodules are commits in another repo, not blobs
+        if entry.kind() == Some(ObjectType::Blob) {
+            let name = String::from_utf8_lossy(entry.name_bytes());
+            entries.push((format!("{}{}", dir, name), entry.id()));
+        }
+
+        TreeWalkResult::Ok
+    })?;
+
+    for (path, id) in entries {
+        let blob = repo.find_blob(id)?;
+
+        conn.prepare_cached(
+            "INSERT OR IGNORE INTO blobs (commit_id, commit_full_id, path, size, is_binary, repo) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?
+        .execute(params![
+            commit_id,
+            commit.id().to_string(),
+            path,
+            blob.size(),
+            blob.is_binary(),
+            ctx.repo_name
+        ])?;
+    }
+
+    Ok(())
+}
+
+// Function to insert the commit that last changed each line of a file, as of a revision,
+// into the `blame` table. Blaming is slow for long histories, so this is only done on request
+fn blame_file(
+    conn: &Connection,
+    repo: &Repository,
+    rev: &str,
+    path: &str,
+    ctx: &LoadContext,
+) -> Result<(), Error> {
+    let commit = resolve_commit(repo, rev)?;
+    let blame = repo.blame_file(
+        Path::new(path),
+        Some(BlameOptions::new().newest_commit(commit.id())),
+    )?;
+
+    // Replace the results of any previous blame of the same file, as the revision may have moved
+    conn.execute(
+        "DELETE FROM blame WHERE rev = ?1 AND path = ?2 AND repo = ?3",
+        params![rev, path, ctx.repo_name],
+    )?;
+
+    // Each hunk is a run of consecutive lines last changed by the same commit
+    for hunk in blame.iter() {
+        let commit_id = hunk.final_commit_id();
+        let author = hunk.final_signature().name().map(|name| name.to_string());
+
+        for offset in 0..hunk.lines_in_hunk() {
+            conn.prepare_cached(
+                "INSERT INTO blame (rev, path, line_no, final_commit_id, final_commit_full_id, orig_line_no, author, repo) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?
+            .execute(params![
+                rev,
+                path,
+                hunk.final_start_line() + offset,
+                abbrev(commit_id, ctx.abbrev_len),
+                commit_id.to_string(),
+                hunk.orig_start_line() + offset,
+                author,
+                ctx.repo_name,
+            ])?;
+        }
+    }
+
+    Ok(())
+}
+
+// Function to insert the signature of a commit into the `signatures` table, returning the
+// commit id, or None if the commit is unsigned. The signature is not verified, so who
+// signed it is unknown: the committer is stored as the claimed author of the signature
+fn load_signature(
+    conn: &Connection,
+    repo: &Repository,
+    rev: &str,
+    ctx: &LoadContext,
+) -> Result<Option<Oid>, Error> {
+    let commit = resolve_commit(repo, rev)?;
+
+    let (signature, signed_data) = match repo.extract_signature(&commit.id(), None) {
+        Ok((signature, signed_data)) => (
+            String::from_utf8_lossy(&signature).to_string(),
+            String::from_utf8_lossy(&signed_data).to_string(),
+        ),
+        Err(err) if err.code() == ErrorCode::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let committer = commit.committer();
+
+    conn.prepare_cached(
+        "INSERT OR REPLACE INTO signatures (commit_id, commit_full_id, signature_type, committer, committer_email, signature, signed_data, repo) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?
+    .execute(params![
+        abbrev(commit.id(), ctx.abbrev_len),
+        commit.id().to_string(),
+        signature_type(&signature),
+        signature_name(&committer),
+        committer.email(),
+        signature,
+        signed_data,
+        ctx.repo_name,
+    ])?;
+
+    Ok(Some(commit.id()))
+}
+
+// Function to make a path relative to the root of the repo, as paths in diffs are. It can be
+// given relative to the root already, or as an absolute path within the working tree
+fn repo_relative_path(repo: &Repository, path: &str) -> String {
+    let path = Path::new(path);
+    let path = repo
+        .workdir()
+        .and_then(|workdir| path.strip_prefix(workdir).ok())
+        .unwrap_or(path);
+
+    let path = path.to_string_lossy();
+    path.trim_start_matches("./")
+        .trim_end_matches('/')
+        .to_string()
+}
+
+// Function to insert the commits in the history of HEAD that changed a path (a file, or any
+// file in a directory) into the `path_history` table, like `git log -- <path>`. Each commit
+// is diffed with its first parent, so this is only done on request
+fn load_path_history(
+    conn: &Connection,
+    repo: &Repository,
+    path: &str,
+    ctx: &LoadContext,
+) -> Result<(), Error> {
+    // Replace the results of any previous `log`, as the table holds the history of one path
+    conn.execute(
+        "DELETE FROM path_history WHERE repo = ?1",
+        params![ctx.repo_name],
+    )?;
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+
+    for (count, id) in revwalk.enumerate() {
+        report_progress(ctx, "Searching commits", count);
+
+        let commit = repo.find_commit(id?)?;
+        let diff = diff_commit(repo, &commit, Some(DiffOptions::new().pathspec(path)))?;
+
+        for delta in diff.deltas() {
+            conn.prepare_cached(
+                "INSERT OR IGNORE INTO path_history (commit_id, commit_full_id, path, status, repo) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute(params![
+                abbrev(commit.id(), ctx.abbrev_len),
+                commit.id().to_string(),
+                delta_path(&delta),
+                delta_status_to_string(delta.status()),
+                ctx.repo_name,
+            ])?;
+        }
+    }
+
+    clear_progress(ctx);
+
+    Ok(())
+}
+
+// Function to insert the branches with commits that aren't reachable from HEAD into the
+// temporary `unmerged` table. It is reloaded each time, as branches may have moved
+fn load_unmerged(conn: &Connection, repo: &Repository, ctx: &LoadContext) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TEMP TABLE IF NOT EXISTS unmerged (
+             repo                TEXT NOT NULL,
+             name                TEXT NOT NULL,
+             type                TEXT NOT NULL,
+             head_commit_id      TEXT NOT NULL,
+             head_commit_full_id TEXT NOT NULL,
+             ahead               INTEGER NOT NULL,
+             PRIMARY KEY (repo, name, type)
+         )",
+    )?;
+    conn.execute(
+        "DELETE FROM unmerged WHERE repo = ?1",
+        params![ctx.repo_name],
+    )?;
+
+    let head_id = repo.head()?.peel_to_commit()?.id();
+
+    for branch in repo.branches(None)? {
+        let (branch, branch_type) = branch?;
+
+        // The current branch is merged into HEAD by definition
+        if branch.is_head() {
+            continue;
+        }
+
+        let tip_id = branch.get().peel_to_commit()?.id();
+        if tip_id == head_id || repo.graph_descendant_of(head_id, tip_id)? {
+            continue;
+        }
+
+        // The number of commits on the branch that HEAD doesn't have
+        let (ahead, _) = repo.graph_ahead_behind(tip_id, head_id)?;
+
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO unmerged (name, type, head_commit_id, head_commit_full_id, ahead, repo) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?
+        .execute(params![
+            String::from_utf8_lossy(branch.name_bytes()?),
+            match branch_type {
+                BranchType::Local => "local",
+                BranchType::Remote => "remote",
+            },
+            abbrev(tip_id, ctx.abbrev_len),
+            tip_id.to_string(),
+            ahead,
+            ctx.repo_name,
+        ])?;
+    }
+
+    Ok(())
+}
+
+// Function to convert the status of a file to a readable String, e.g. "staged modified, new".
+// A file can have several, e.g. when a staged file is modified again
+fn status_to_string(status: Status) -> String {
+    let names = [
+        (Status::INDEX_NEW, "staged new"),
+        (Status::INDEX_MODIFIED, "staged modified"),
+        (Status::INDEX_DELETED, "staged deleted"),
+        (Status::INDEX_RENAMED, "staged renamed"),
+        (Status::INDEX_TYPECHANGE, "staged typechange"),
+        (Status::WT_NEW, "new"),
+        (Status::WT_MODIFIED, "modified"),
+        (Status::WT_DELETED, "deleted"),
+        (Status::WT_RENAMED, "renamed"),
+        (Status::WT_TYPECHANGE, "typechange"),
+        (Status::CONFLICTED, "conflicted"),
+    ];
+
+    names
+        .iter()
+        .filter(|(flag, _)| status.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+// Function to insert the uncommitted changes in the working tree and index into the
+// `status` table. They are reloaded each time, as files may have changed
+fn load_status(conn: &Connection, repo: &Repository, ctx: &LoadContext) -> Result<(), Error> {
+    conn.execute("DELETE FROM status WHERE repo = ?1", params![ctx.repo_name])?;
+
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).include_ignored(false);
+
+    for entry in repo.statuses(Some(&mut options))?.iter() {
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO status (path, status, repo) VALUES (?1, ?2, ?3)",
+        )?
+        .execute(params![
+            String::from_utf8_lossy(entry.path_bytes()),
+            status_to_string(entry.status()),
+            ctx.repo_name,
+        ])?;
+    }
+
+    Ok(())
+}
+
+// Function to insert a Git reference into the `refs` table
+fn insert_ref(conn: &Connection, reference: &Reference, ctx: &LoadContext) -> Result<(), Error> {
+    let ref_type = match reference.kind() {
+        Some(ReferenceType::Symbolic) => "symbolic",
+        _ => "direct",
+    };
+
+    // A symbolic ref is peeled to the object its chain of refs ends at. It has no
+    // target if the chain ends at a missing ref, e.g. an unborn branch
+    let target_id = reference
+        .resolve()
+        .ok()
+        .and_then(|r| r.target())
+        .map(|id| id.to_string());
+
+    conn.prepare_cached(
+        "INSERT OR REPLACE INTO refs (name, target_id, symbolic_target, type, repo) VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?
+    .execute(params![
+        String::from_utf8_lossy(reference.name_bytes()),
+        target_id,
+        reference
+            .symbolic_target_bytes()
+            .map(|t| String::from_utf8_lossy(t).to_string()),
+        ref_type,
+        ctx.repo_name,
+    ])?;
+
+    Ok(())
+}
+
+// Function to insert all references, including HEAD, notes and custom refs, into the
+// `refs` table. They are reloaded each time, as refs may have moved
+fn load_refs(conn: &Connection, repo: &Repository, ctx: &LoadContext) -> Result<(), Error> {
+    conn.execute("DELETE FROM refs WHERE repo = ?1", params![ctx.repo_name])?;
+
+    // HEAD isn't listed with the other references
+    if let Ok(head) = repo.find_reference("HEAD") {
+        insert_ref(conn, &head, ctx)?;
+    }
+
+    for reference in repo.references()? {
+        insert_ref(conn, &reference?, ctx)?;
+    }
+
+    Ok(())
+}
+
+// Function to insert the files changed between two revisions into the `diffs` table,
+// and print a summary of the changes, like `git diff --shortstat`
+fn diff_revs(
+    conn: &Connection,
+    repo: &Repository,
+    rev_a: &str,
+    rev_b: &str,
+    ctx: &LoadContext,
+) -> Result<(), Error> {
+    let tree_a = resolve_commit(repo, rev_a)?.tree()?;
+    let tree_b = resolve_commit(repo, rev_b)?.tree()?;
+    let diff = repo.diff_tree_to_tree(Some(&tree_a), Some(&tree_b), None)?;
+
+    // Replace the results of any previous diff of the same revisions, as they may have moved
+    conn.execute(
+        "DELETE FROM diffs WHERE rev_a = ?1 AND rev_b = ?2 AND repo = ?3",
+        params![rev_a, rev_b, ctx.repo_name],
+    )?;
+
+    let (mut total_insertions, mut total_deletions) = (0, 0);
+
+    for (delta_idx, delta) in diff.deltas().enumerate() {
+        let (insertions, deletions) = delta_line_stats(&diff, delta_idx)?;
+        total_insertions += insertions.unwrap_or(0);
+        total_deletions += deletions.unwrap_or(0);
+
+        conn.prepare_cached(
+            "INSERT OR IGNORE INTO diffs (rev_a, rev_b, path, status, insertions, deletions, repo) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?
+        .execute(params![
+            rev_a,
+            rev_b,
+            delta_path(&delta),
+            delta_status_to_string(delta.status()),
+            insertions,
+            deletions,
+            ctx.repo_name,
+        ])?;
+    }
+
+    println!(
+        "{} files changed, {} insertions(+), {} deletions(-)",
+        diff.deltas().len(),
+        total_insertions,
+        total_deletions
+    );
+
+    Ok(())
+}
+
+// Function to build the SQL query for the `search` command, which finds the commits whose
+// messages contain all of the given terms (case-insensitively, for ASCII letters)
+fn search_sql(terms: &[&str]) -> String {
+    let conditions: Vec<String> = terms
+        .iter()
+        .map(|term| {
+            // Escape the term for a string literal, and the LIKE wildcards `%` and `_`
+            let pattern = term
+                .replace('\'', "''")
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("message LIKE '%{}%' ESCAPE '\\'", pattern)
+        })
+        .collect();
+
+    format!(
+        "SELECT id, date, author, subject FROM commits WHERE {} ORDER BY date_epoch DESC",
+        conditions.join(" AND ")
+    )
+}
+
+// Function to build the SQL query for the `shortlog` command, which counts the commits of
+// each author, like `git shortlog -sn`, optionally within a date range
+fn shortlog_sql(words: &[&str]) -> Result<String, String> {
+    let mut conditions = Vec::new();
+    let mut words = words.iter();
+
+    while let Some(&word) = words.next() {
+        let date = words
+            .next()
+            .ok_or_else(|| format!("`{}` needs a date", word))?;
+
+        // Like `traverse`, the range is compared with the commit datetime
+        match word {
+            "--since" => conditions.push(format!(
+                "committer_date_epoch >= {}",
+                parse_traverse_date(date, false)?
+            )),
+            "--until" => conditions.push(format!(
+                "committer_date_epoch <= {}",
+                parse_traverse_date(date, true)?
+            )),
+            _ => return Err(format!("Unknown option `{}`", word)),
+        }
+    }
+
+    let filter = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", conditions.join(" AND "))
+    };
+
+    Ok(format!(
+        "SELECT author, count(*) AS commits FROM commits{} GROUP BY author ORDER BY commits DESC, author",
+        filter
+    ))
+}
+
+// Function to get the rest of a command's input after its leading words, e.g. a path or a
+// query, which can contain spaces
+fn rest_of_input<'a>(input: &'a str, words: &[&str]) -> &'a str {
+    words
+        .iter()
+        .fold(input.trim_start(), |rest, word| {
+            rest.strip_prefix(word).unwrap_or(rest).trim_start()
+        })
+        .trim_end()
+}
+
+// Function to run a command entered on a single line.
+// Returns false if the program should exit, or None if the input is not a command
+fn run_command(
+    conn: &Connection,
+    repo: &Repository,
+    args: &Args,
+    settings: &mut Settings,
+    loaded_tips: &mut HashSet<Oid>,
+    input: &str,
+) -> Option<bool> {
+    let name = repo_name(repo);
+    let ctx = LoadContext {
+        repo_name: &name,
+        abbrev_len: usize::from(args.abbrev),
+        show_progress: show_progress(args),
+    };
+
+    match input.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        [] => {}
+        ["exit"] | ["quit"] => return Some(false),
+        ["help"] => {
+            println!("Available commands:");
+            println!(" - `exit` or `quit` (or Ctrl-D): Exit the program.");
+            println!(" - `help`: Display this help message.");
+            println!(" - `traverse <rev>`: Traverse commit history from a commit id, branch or tag, and insert each commit into the database.");
+            println!(" - `traverse <rev a>..<rev b>`: Traverse only the commits reachable from `rev b` but not from `rev a`.");
+            println!(" - `traverse <rev> [--since <date>] [--until <date>]`: Traverse only the commits made within the date range (YYYY-MM-DD or ISO 8601).");
+            println!(" - `traverse --all`: Traverse the history of every local and remote branch.");
+            println!(" - `traverse --first-parent <rev>`: Traverse only the first parent of each merge commit, like `git log --first-parent`.");
+            println!(" - `traverse <rev> [--topo-order] [--date-order] [--reverse]`: Traverse in topological or commit date order, or in reverse.");
+            println!(" - `merge-base <rev a> <rev b>`: Show the best common ancestor of two revisions, and insert it into the database.");
+            println!(" - `diff <rev a> <rev b>`: Insert the files changed between two revisions into the `diffs` table, and summarize the changes.");
+            println!(" - `refs`: List all references with the ids they point to, and insert them into the `refs` table.");
+            println!(" - `blame <rev> <path>`: Insert the commit that last changed each line of a file into the `blame` table.");
+            println!(" - `log <path>`: List the commits that changed a file or directory, like `git log -- <path>`, and insert them into the `path_history` table.");
+            println!(" - `verify <rev>`: Show the (unverified) signature and committer of a commit, and insert them into the `signatures` table.");
+            println!(" - `unmerged`: List the branches with commits that aren't reachable from HEAD, and insert them into the `unmerged` table.");
+            println!(" - `status`: List the uncommitted changes, like `git status`, and insert them into the `status` table.");
+            println!(" - `load-tree <rev>`: Insert the files in the tree of a commit, with their sizes, into the `blobs` table.");
+            println!(
+                " - `search <terms>`: Find the commits whose messages contain all of the terms."
+            );
+            println!(" - `shortlog [--since <date>] [--until <date>]`: Count the commits of each author, like `git shortlog -sn`.");
+            println!(" - `.tables`: List the tables that can be queried.");
+            println!(" - `.schema [table]`: Show the CREATE TABLE statements of all tables, or of the given table.");
+            println!(" - `.mode [table|json|csv|markdown|line]`: Set the format to display results in, or show the current format. `line` displays each row as `column = value` lines.");
+            println!(
+                " - `.dump [path]`: Export the database as SQL statements, to the file if given."
+            );
+            println!(" - `.read <path>`: Run the SQL statements in a file.");
+            println!(" - `.view save <name> <sql>`: Save a query as a view, which can be queried like a table.");
+            println!(" - `.view run <name>`: Run a saved view.");
+            println!(" - `.view list`: List the saved views.");
+            println!(" - `.view drop <name>`: Delete a saved view.");
+            println!(
+                " - `.eqp <sql>`: Show how SQLite runs a query, e.g. whether it uses an index."
+            );
+            println!(" - `.attach <path> <alias>`: Attach another SQLite database, so its tables can be queried as `<alias>.<table>`.");
+            println!(" - `.detach <alias>`: Detach an attached database.");
+            println!(" - `.vacuum`: Rebuild the database to reclaim unused space, and show its size in pages before and after.");
+            println!(" - `.output [path]`: Write query results to a file, or to the terminal again if no path is given.");
+            println!(" - `.save <path>`: Save the database to a file, which can be reopened with `--db <path>`.");
+            println!(" - `.timer on|off`: Show how long each query takes to run.");
+            println!(" - `.nullvalue [text]`: Display NULL values as the text, or as the default for the format if no text is given.");
+            println!(" - `.width [n]`: Set the width of result tables, or fit them to the terminal if no width is given.");
+            println!(" - Enter SQL at the prompt to see results. SQL can span multiple lines, and runs once it ends with `;`.");
+        }
+        [".tables"] => {
+            if let Err(err) = print_tables(conn) {
+                eprintln!("{err}");
+            }
+        }
+        [".schema"] => {
+            if let Err(err) = print_schema(conn, None) {
+                eprintln!("{err}");
+            }
+        }
+        [".schema", table] => {
+            if let Err(err) = print_schema(conn, Some(table)) {
+                eprintln!("{err}");
+            }
+        }
+        [".mode"] => {
+            if let Some(value) = settings.format.to_possible_value() {
+                println!("{}", value.get_name());
+            }
+        }
+        [".mode", mode] => match Format::from_str(mode, true) {
+            Ok(format) => settings.format = format,
+            Err(_) => {
+                let modes: Vec<String> = Format::value_variants()
+                    .iter()
+                    .filter_map(|f| f.to_possible_value())
+                    .map(|v| v.get_name().to_string())
+                    .collect();
+                eprintln!("Invalid mode `{}`. Use one of: {}", mode, modes.join(", "));
+            }
+        },
+        [".dump", ..] => {
+            let path = rest_of_input(input, &[".dump"]);
 
-    // Print the table and the row count
-    println!("{table}");
-    println!("Rows returned: {}", row_count);
+            match dump_sql(conn) {
+                Ok(dump) if path.is_empty() => print!("{}", dump),
+                Ok(dump) => {
+                    if let Err(err) = std::fs::write(path, dump) {
+                        eprintln!("Could not write to {}. {}", path, err);
+                    }
+                }
+                Err(err) => eprintln!("{err}"),
+            }
+        }
+        [".output"] => settings.output = None,
+        [".output", _, ..] => {
+            let path = rest_of_input(input, &[".output"]);
 
-    // Show tip if no results returned and SQL query contains `commits`
-    if row_count == 0 && sql.contains("commits") {
-        println!("Tip: use the `traverse <commit id>` command to insert commit history")
+            // Like sqlite3, the file is overwritten, and the results of each query appended
+            match File::create(path) {
+                Ok(file) => settings.output = Some(file),
+                Err(err) => eprintln!("Could not write to {}. {}", path, err),
+            }
+        }
+        [".read", _, ..] => {
+            let path = rest_of_input(input, &[".read"]);
+
+            if let Err(err) = read_sql_file(conn, settings, path) {
+                eprintln!("Could not read {}. {}", path, err);
+            }
+        }
+        [".view", "save", view, _, ..] => {
+            let sql = rest_of_input(input, &[".view", "save", view]);
+
+            if let Err(err) = in_transaction(conn, |tx| save_view(tx, view, sql)) {
+                eprintln!("Could not save view {}. {}", view, err);
+            }
+        }
+        [".view", "run", view] => {
+            let sql = format!("SELECT * FROM {}", quote_identifier(view));
+
+            if let Err(err) = run_sql_query(conn, &sql, settings) {
+                eprintln!("{err}");
+            }
+        }
+        [".view", "list"] => {
+            let sql = "SELECT name, sql FROM sqlite_master WHERE type = 'view' ORDER BY name";
+
+            if let Err(err) = run_sql_query(conn, sql, settings) {
+                eprintln!("{err}");
+            }
+        }
+        [".view", "drop", view] => {
+            let sql = format!("DROP VIEW {}", quote_identifier(view));
+
+            if let Err(err) = conn.execute(&sql, ()) {
+                eprintln!("Could not drop view {}. {}", view, err);
+            }
+        }
+        [".eqp", _, ..] => {
+            let query = rest_of_input(input, &[".eqp"]);
+            let sql = format!("EXPLAIN QUERY PLAN {}", query.trim_end_matches(';'));
+
+            if let Err(err) = run_sql_query(conn, &sql, settings) {
+                report_sql_error(&sql, &err);
+            }
+        }
+        [".attach", path, alias] => {
+            // SQLite would create a new, empty database for a missing path
+            if !Path::new(path).exists() {
+                eprintln!("Could not attach {}. No such file", path);
+            } else if let Err(err) = conn.execute("ATTACH DATABASE ?1 AS ?2", params![path, alias])
+            {
+                eprintln!("Could not attach {}. {}", path, err);
+            }
+        }
+        [".detach", alias] => {
+            if let Err(err) = conn.execute("DETACH DATABASE ?1", params![alias]) {
+                eprintln!("Could not detach {}. {}", alias, err);
+            }
+        }
+        [".vacuum"] => {
+            if let Err(err) = vacuum(conn) {
+                eprintln!("Could not vacuum. {}", err);
+            }
+        }
+        [".save", _, ..] => {
+            let path = rest_of_input(input, &[".save"]);
+
+            // Copy the database to the file with SQLite's backup API
+            if let Err(err) = conn.backup(DatabaseName::Main, path, None) {
+                eprintln!("Could not save to {}. {}", path, err);
+            }
+        }
+        ["merge-base", rev_a, rev_b] => {
+            match in_transaction(conn, |tx| {
+                merge_base(tx, repo, rev_a, rev_b, args.files, &ctx)
+            }) {
+                Ok(id) => {
+                    let sql = format!(
+                        "SELECT id, full_id, author, date, subject FROM commits WHERE full_id = '{}' AND repo = {}",
+                        id,
+                        value_to_sql(Value::Text(name.clone()))
+                    );
+
+                    if let Err(err) = run_sql_query(conn, &sql, settings) {
+                        eprintln!("{err}");
+                    }
+                }
+                Err(err) => eprintln!("merge-base error. {}", err),
+            }
+        }
+        ["diff", rev_a, rev_b] => {
+            if let Err(err) = in_transaction(conn, |tx| diff_revs(tx, repo, rev_a, rev_b, &ctx)) {
+                eprintln!("diff error. {}", err);
+            }
+        }
+        ["refs"] => match in_transaction(conn, |tx| load_refs(tx, repo, &ctx)) {
+            Ok(()) => {
+                let sql = format!(
+                    "SELECT name, target_id, type FROM refs WHERE repo = {} ORDER BY name",
+                    value_to_sql(Value::Text(name.clone()))
+                );
+
+                if let Err(err) = run_sql_query(conn, &sql, settings) {
+                    eprintln!("{err}");
+                }
+            }
+            Err(err) => eprintln!("refs error. {}", err),
+        },
+        ["blame", rev, _, ..] => {
+            let path = rest_of_input(input, &["blame", rev]);
+
+            if let Err(err) = in_transaction(conn, |tx| blame_file(tx, repo, rev, path, &ctx)) {
+                eprintln!("blame error. {}", err);
+            }
+        }
+        ["log", _, ..] => {
+            let path = repo_relative_path(repo, rest_of_input(input, &["log"]));
+
+            match in_transaction(conn, |tx| load_path_history(tx, repo, &path, &ctx)) {
+                Ok(()) => {
+                    let sql = format!(
+                        "SELECT commit_id, path, status FROM path_history WHERE repo = {} ORDER BY rowid",
+                        value_to_sql(Value::Text(name.clone()))
+                    );
+
+                    if let Err(err) = run_sql_query(conn, &sql, settings) {
+                        eprintln!("{err}");
+                    }
+                }
+                Err(err) => eprintln!("log error. {}", err),
+            }
+        }
+        ["verify", rev] => match in_transaction(conn, |tx| load_signature(tx, repo, rev, &ctx)) {
+            Ok(Some(id)) => {
+                let sql = format!(
+                    "SELECT commit_id, signature_type, committer, committer_email, signature FROM signatures WHERE commit_full_id = '{}' AND repo = {}",
+                    id,
+                    value_to_sql(Value::Text(name.clone()))
+                );
+
+                if let Err(err) = run_sql_query(conn, &sql, settings) {
+                    eprintln!("{err}");
+                }
+                eprintln!("Note: the signature is not verified, so the signer is unknown. The committer only claims to have signed it. To verify it, use `git verify-commit`.");
+            }
+            Ok(None) => println!("`{}` is not signed.", rev),
+            Err(err) => eprintln!("verify error. {}", err),
+        },
+        ["unmerged"] => match in_transaction(conn, |tx| load_unmerged(tx, repo, &ctx)) {
+            Ok(()) => {
+                let sql = format!(
+                    "SELECT name, type, head_commit_id, ahead FROM unmerged WHERE repo = {} ORDER BY type, name",
+                    value_to_sql(Value::Text(name.clone()))
+                );
+
+                if let Err(err) = run_sql_query(conn, &sql, settings) {
+                    eprintln!("{err}");
+                }
+            }
+            Err(err) => eprintln!("unmerged error. {}", err),
+        },
+        ["status"] => match in_transaction(conn, |tx| load_status(tx, repo, &ctx)) {
+            Ok(()) => {
+                let sql = format!(
+                    "SELECT path, status FROM status WHERE repo = {} ORDER BY path",
+                    value_to_sql(Value::Text(name.clone()))
+                );
+
+                if let Err(err) = run_sql_query(conn, &sql, settings) {
+                    eprintln!("{err}");
+                }
+            }
+            Err(err) => eprintln!("status error. {}", err),
+        },
+        ["load-tree", rev] => {
+            if let Err(err) = in_transaction(conn, |tx| load_tree(tx, repo, rev, &ctx)) {
+                eprintln!("load-tree error. {}", err);
+            }
+        }
+        ["search", terms @ ..] if !terms.is_empty() => {
+            if let Err(err) = run_sql_query(conn, &search_sql(terms), settings) {
+                eprintln!("{err}");
+            }
+        }
+        ["shortlog", words @ ..] => match shortlog_sql(words) {
+            Ok(sql) => {
+                if let Err(err) = run_sql_query(conn, &sql, settings) {
+                    eprintln!("{err}");
+                }
+            }
+            Err(err) => eprintln!("shortlog error. {}", err),
+        },
+        [".timer", "on"] => settings.timer = true,
+        [".timer", "off"] => settings.timer = false,
+        [".nullvalue"] => settings.nullvalue = None,
+        [".nullvalue", _, ..] => {
+            let text = rest_of_input(input, &[".nullvalue"]);
+            settings.nullvalue = Some(text.to_string());
+        }
+        [".width"] => settings.width = None,
+        [".width", width] => match width.parse() {
+            Ok(w) => settings.width = Some(w),
+            Err(err) => eprintln!("Invalid width. {}", err),
+        },
+        ["traverse", words @ ..] if !words.is_empty() => match parse_traverse_args(words) {
+            Ok((rev, options)) => {
+                if let Err(err) = in_transaction(conn, |tx| {
+                    traverse(tx, repo, rev, &options, args.files, &ctx, loaded_tips)
+                }) {
+                    eprintln!("traverse error. {}", err);
+                }
+            }
+            Err(err) => eprintln!("traverse error. {}", err),
+        },
+        _ => return None,
     }
 
-    Ok(())
+    Some(true)
 }
 
-// Function to traverse commit history and insert into database
-fn traverse(conn: &Connection, repo: &Repository, commit_id: Option<&str>) -> Result<(), Error> {
-    // Create a revwalk to traverse the commit history
-    let mut revwalk = repo.revwalk()?;
-    if let Some(c_id) = commit_id {
-        let commit = repo.find_commit_by_prefix(c_id)?;
-        revwalk.push(commit.id())?;
+// Function to report an error from running SQL, showing the SQL with a caret under the
+// position of the error when SQLite reports one
+fn report_sql_error(sql: &str, err: &Error) {
+    let (msg, offset) = match err {
+        Error::SqlError(rusqlite::Error::SqlInputError { msg, offset, .. }) => {
+            (format!("SQL error: {}", msg), usize::try_from(*offset).ok())
+        }
+        _ => (err.to_string(), None),
+    };
+
+    eprintln!("{}", msg);
+
+    // Without a position, echo the statement that failed
+    let Some(offset) = offset.filter(|o| *o <= sql.len() && sql.is_char_boundary(*o)) else {
+        eprintln!("in: {}", sql);
+        return;
+    };
+
+    let mut line_start = 0;
+    for line in sql.split('\n') {
+        eprintln!("  {}", line);
+
+        let line_end = line_start + line.len();
+        if (line_start..=line_end).contains(&offset) {
+            let column = sql[line_start..offset].chars().count();
+            eprintln!("  {}^", " ".repeat(column));
+        }
+
+        line_start = line_end + 1;
+    }
+}
+
+// Function to split SQL into its statements. `;` only ends a statement outside of string
+// literals, quoted identifiers and comments, and `--` comments are removed. Returns the
+// complete statements, and the rest of the SQL, which is an unfinished statement, if any
+fn split_sql(sql: &str) -> (Vec<String>, String) {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => {
+                let statement = current.trim();
+                if !statement.is_empty() {
+                    statements.push(statement.to_string());
+                }
+                current.clear();
+            }
+            // Skip a line comment, up to the end of the line
+            '-' if chars.peek() == Some(&'-') => {
+                while chars.next_if(|&next| next != '\n').is_some() {}
+            }
+            // Keep a block comment, up to its end
+            '/' if chars.peek() == Some(&'*') => {
+                current.push(c);
+                current.push(chars.next().unwrap_or('*'));
+
+                let mut prev = ' ';
+                for next in chars.by_ref() {
+                    current.push(next);
+                    if prev == '*' && next == '/' {
+                        break;
+                    }
+                    prev = next;
+                }
+            }
+            // Keep a string literal or quoted identifier, up to its closing quote. A doubled
+            // quote (an escaped quote) closes it and opens it again
+            '\'' | '"' | '`' | '[' => {
+                let closing = if c == '[' { ']' } else { c };
+                current.push(c);
+
+                for next in chars.by_ref() {
+                    current.push(next);
+                    if next == closing {
+                        break;
+                    }
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+
+    let rest = if current.trim().is_empty() {
+        String::new()
     } else {
-        revwalk.push_head()?;
+        current.trim_start().to_string()
+    };
+
+    (statements, rest)
+}
+
+// Function to run each of the given SQL statements. An error in one statement is
+// reported, and doesn't stop the rest from running. Returns the first error, if any
+fn run_statements(
+    conn: &Connection,
+    statements: &[String],
+    settings: &Settings,
+) -> Result<(), Error> {
+    let mut result = Ok(());
+
+    for statement in statements {
+        if let Err(err) = run_sql_query(conn, statement, settings) {
+            report_sql_error(statement, &err);
+
+            if result.is_ok() {
+                result = Err(err);
+            }
+        }
     }
 
-    // Iterate over Git commit history and insert each commit into the database
-    for commit_id in revwalk {
-        let commit_id = commit_id?;
-        let commit = repo.find_commit(commit_id)?;
+    result
+}
+
+// Function to split SQL into its statements, including a last statement that doesn't
+// end with `;`, and run each of them
+fn run_sql(conn: &Connection, sql: &str, settings: &Settings) -> Result<(), Error> {
+    let (mut statements, rest) = split_sql(sql);
+    if !rest.is_empty() {
+        statements.push(rest);
+    }
+
+    run_statements(conn, &statements, settings)
+}
+
+// Function to run buffered SQL, if any, and clear the buffer
+fn flush_sql(conn: &Connection, settings: &Settings, buffer: &mut String) -> Result<(), Error> {
+    let mut result = Ok(());
 
-        insert_commit(&conn, &commit)?;
+    if !buffer.is_empty() {
+        if let Err(err) = run_sql_query(conn, buffer, settings) {
+            report_sql_error(buffer, &err);
+            result = Err(err);
+        }
+
+        buffer.clear();
     }
 
+    result
+}
+
+// Function to run each of the SQL statements in a file
+fn read_sql_file(conn: &Connection, settings: &Settings, path: &str) -> std::io::Result<()> {
+    let sql = std::fs::read_to_string(path)?;
+
+    // Errors in the statements are reported as they run
+    let _ = run_sql(conn, &sql, settings);
+
     Ok(())
 }
 
-// Constants for the terminal prompt and the initial SQL query
+// What to do after a line of user input is handled
+enum LineOutcome {
+    Continue,
+    // An SQL statement failed (and was reported). Reading lines continues
+    SqlFailed(Error),
+    Exit,
+}
+
+// Function to handle a line of user input. Commands run immediately, while SQL is
+// buffered until a statement is complete (ends with `;`). A line can complete
+// several statements, which run in turn
+fn handle_line(
+    conn: &Connection,
+    repo: &Repository,
+    args: &Args,
+    settings: &mut Settings,
+    loaded_tips: &mut HashSet<Oid>,
+    buffer: &mut String,
+    line: &str,
+) -> LineOutcome {
+    // Commands are only recognized at the start of a statement
+    if buffer.is_empty() {
+        match run_command(conn, repo, args, settings, loaded_tips, line) {
+            Some(true) => return LineOutcome::Continue,
+            Some(false) => return LineOutcome::Exit,
+            None => {}
+        }
+    } else {
+        buffer.push('\n');
+    }
+
+    buffer.push_str(line);
+
+    // Keep an unfinished statement in the buffer, for the next lines to complete.
+    // Errors in the statements are reported as they run
+    let (statements, rest) = split_sql(buffer);
+    *buffer = rest;
+
+    match run_statements(conn, &statements, settings) {
+        Ok(()) => LineOutcome::Continue,
+        Err(err) => LineOutcome::SqlFailed(err),
+    }
+}
+
+// SQL keywords suggested by tab-completion
+const SQL_KEYWORDS: &[&str] = &[
+    "AND", "AS", "ASC", "BETWEEN", "BY", "CASE", "COUNT", "DESC", "DISTINCT", "ELSE", "END",
+    "EXISTS", "FROM", "GLOB", "GROUP", "HAVING", "IN", "INNER", "IS", "JOIN", "LEFT", "LIKE",
+    "LIMIT", "NOT", "NULL", "OFFSET", "ON", "OR", "ORDER", "SELECT", "THEN", "UNION", "WHEN",
+    "WHERE", "WITH",
+];
+
+// Line editor helper for tab-completion of table names, column names and SQL keywords
+struct SqlHelper {
+    words: Vec<String>,
+}
+
+impl SqlHelper {
+    // Function to reload the table and column names, as tables can change during a session
+    fn load_words(&mut self, conn: &Connection) {
+        let names = conn
+            .prepare(
+                "SELECT m.name, p.name FROM sqlite_master m, pragma_table_info(m.name) p
+                 WHERE m.type IN ('table', 'view')",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| Ok([row.get(0)?, row.get(1)?]))?
+                    .collect::<Result<Vec<[String; 2]>>>()
+            })
+            .unwrap_or_default();
+
+        self.words = names.into_iter().flatten().collect();
+        self.words
+            .extend(SQL_KEYWORDS.iter().map(|k| k.to_string()));
+        self.words.sort();
+        self.words.dedup();
+    }
+}
+
+// Function to find where the partial identifier before the cursor starts: after the last
+// character before it that isn't a word character, which can be multibyte (e.g., `’`)
+fn word_start(line: &str, pos: usize) -> usize {
+    line[..pos]
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !(c.is_alphanumeric() || *c == '_'))
+        .map_or(0, |(i, c)| i + c.len_utf8())
+}
+
+impl Completer for SqlHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = word_start(line, pos);
+        let prefix = line[start..pos].to_lowercase();
+
+        if prefix.is_empty() {
+            return Ok((start, Vec::new()));
+        }
+
+        let candidates = self
+            .words
+            .iter()
+            .filter(|word| word.to_lowercase().starts_with(&prefix))
+            .cloned()
+            .collect();
+
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for SqlHelper {
+    type Hint = String;
+}
+
+impl Highlighter for SqlHelper {}
+
+impl Validator for SqlHelper {}
+
+impl Helper for SqlHelper {}
+
+// Function to get the command to page results through, like `git log`.
+// Paging is only enabled with `--pager`, and only when output goes to a terminal
+fn pager_command(args: &Args) -> Option<String> {
+    if !args.pager || !stdout().is_terminal() {
+        return None;
+    }
+
+    // `PAGER` overrides the default, so `PAGER=cat` disables paging.
+    // With `-FRX`, less exits when the output fits on one screen, and leaves it on screen
+    match std::env::var("PAGER") {
+        Ok(pager) if !pager.trim().is_empty() => Some(pager),
+        _ => Some(String::from("less -FRX")),
+    }
+}
+
+// Function to decide whether to color tables, following the `NO_COLOR` convention
+// (https://no-color.org): any non-empty value disables color
+fn use_color(args: &Args) -> bool {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+
+    !args.no_color && !no_color && stdout().is_terminal()
+}
+
+// Function to describe the versions of git-query and the libgit2 and SQLite libraries it uses
+fn version_info() -> String {
+    let (major, minor, patch) = git2::Version::get().libgit2_version();
+
+    format!(
+        "{}\nlibgit2 {}.{}.{}\nSQLite {}",
+        env!("CARGO_PKG_VERSION"),
+        major,
+        minor,
+        patch,
+        rusqlite::version()
+    )
+}
+
+// Constants for the terminal prompts and the initial SQL query
 const TERMINAL_PROMPT: &str = ">> ";
-const INIT_SQL_QUERY: &str = "SELECT * FROM commits ORDER BY date DESC LIMIT 1;";
+const CONTINUATION_PROMPT: &str = ".. ";
+const INIT_SQL_QUERY: &str = "SELECT * FROM commits ORDER BY date_epoch DESC LIMIT 1;";
+
+// Name of the file in the home directory that the prompt history is saved to
+const HISTORY_FILE_NAME: &str = ".git-query_history";
+
+fn main() {
+    if let Err(fatal) = run() {
+        eprintln!("Error: {}", fatal.message);
+        std::process::exit(fatal.exit_code);
+    }
+}
 
-fn main() -> Result<(), String> {
-    // TODO: take repo_path as an option
-    let repo_path = "./";
+// Function to run the program, returning once it should exit
+fn run() -> Result<(), Fatal> {
+    // The version includes those of the linked libraries, for bug reports
+    let matches = Args::command().version(version_info()).get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
 
-    // Open the Git repository
-    let repo = Repository::open(repo_path).map_err(|err| format!("Cannot open repo. {}", err))?;
+    // Open the Git repositories
+    let mut repos = args
+        .repo
+        .iter()
+        .map(Repository::open)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| Fatal::new(EXIT_GIT_ERROR, format!("Cannot open repo. {}", err)))?;
+
+    // Commits whose entire history has been inserted into the database
+    let mut loaded_tips = HashSet::new();
 
     // Initialize the SQLite database with Git commit data
-    let conn = init_db(&repo).map_err(|err| format!("DB error. {}", err))?;
+    let conn = init_db(&mut repos, &args, &mut loaded_tips)
+        .map_err(|err| Fatal::new(err.exit_code(), format!("DB error. {}", err)))?;
+
+    let mut settings = Settings {
+        format: args.format,
+        width: args.width,
+        timer: false,
+        nullvalue: args.nullvalue.clone(),
+        blob_format: args.blob_format,
+        max_rows: args.max_rows,
+        max_col_width: args.max_col_width,
+        quiet: args.quiet,
+        pager: pager_command(&args),
+        color: use_color(&args),
+        output: None,
+    };
+
+    // In non-interactive mode, run only the given SQL query
+    if let Some(sql) = &args.command {
+        return run_sql(&conn, sql, &settings)
+            .map_err(|err| Fatal::new(err.exit_code(), String::from("SQL query failed")));
+    }
+
+    // SQL entered so far for the current statement
+    let mut buffer = String::new();
+
+    // When input is piped in, run each line until end-of-input, without prompts. Like `-c`,
+    // the exit code tells scripts whether any SQL statement failed
+    if !stdin().is_terminal() {
+        let mut sql_error: Option<Error> = None;
+
+        for line in stdin().lines() {
+            let line = line.map_err(|err| {
+                Fatal::new(EXIT_IO_ERROR, format!("Failed to read line. {}", err))
+            })?;
+
+            match handle_line(
+                &conn,
+                &repos[0],
+                &args,
+                &mut settings,
+                &mut loaded_tips,
+                &mut buffer,
+                line.trim(),
+            ) {
+                LineOutcome::Continue => {}
+                LineOutcome::SqlFailed(err) => {
+                    sql_error.get_or_insert(err);
+                }
+                LineOutcome::Exit => break,
+            }
+        }
+
+        // Run the last statement, even if it doesn't end with `;`
+        if let Err(err) = flush_sql(&conn, &settings, &mut buffer) {
+            sql_error.get_or_insert(err);
+        }
+
+        return match sql_error {
+            Some(err) => Err(Fatal::new(
+                err.exit_code(),
+                String::from("SQL statement failed"),
+            )),
+            None => Ok(()),
+        };
+    }
 
-    // Run the initial SQL query and display the result
-    println!("{}{}", TERMINAL_PROMPT, INIT_SQL_QUERY);
-    run_sql_query(&conn, INIT_SQL_QUERY)
-        .map_err(|err| format!("Initial SQL query failed. {}", err))?;
+    // Run the initial SQL query and display the result. A failing query given with
+    // `--init-query` is reported, and the prompt still starts so it can be corrected
+    if !args.no_init_query && !args.quiet {
+        println!("{}{}", TERMINAL_PROMPT, args.init_query);
+        let _ = run_sql(&conn, &args.init_query, &settings);
+    }
+
+    // Create a line editor, with the history from previous sessions.
+    // Like a shell, Tab completes the common prefix of the candidates, then lists them
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .build();
+    let mut editor: Editor<SqlHelper, DefaultHistory> = Editor::with_config(config)
+        .map_err(|err| Fatal::new(EXIT_IO_ERROR, format!("Cannot create line editor. {}", err)))?;
+    editor.set_helper(Some(SqlHelper { words: Vec::new() }));
+    let history_path = std::env::home_dir().map(|home| home.join(HISTORY_FILE_NAME));
+
+    if let Some(path) = &history_path {
+        // There is no history file before the first session
+        let _ = editor.load_history(path);
+    }
 
     // Command loop for running SQL queries from the user
     loop {
-        print!("{}", TERMINAL_PROMPT);
-        // Ensure the prompt is displayed immediately
-        stdout()
-            .flush()
-            .map_err(|err| format!("Flush error. {}", err))?;
-
-        let mut input = String::new();
-        stdin()
-            .read_line(&mut input)
-            .map_err(|err| format!("Failed to read line. {}", err))?;
-
-        let input = input.trim(); // Remove newline characters
-
-        match input.split_whitespace().collect::<Vec<&str>>().as_slice() {
-            [] => {}
-            ["exit"] | ["quit"] => break,
-            ["help"] => {
-                println!("Available commands:");
-                println!(" - `exit` or `quit`: Exit the program.");
-                println!(" - `help`: Display this help message.");
-                println!(" - `traverse <commit id>`: Traverse commit history and insert each commit into the database.");
-                println!(" - Enter SQL at the prompt to see results.");
-            }
-            ["traverse", commit_id] => {
-                if let Err(err) = traverse(&conn, &repo, Some(commit_id)) {
-                    eprintln!("traverse error. {}", err);
-                }
+        // Show the continuation prompt while a statement spans multiple lines
+        let prompt = if buffer.is_empty() {
+            TERMINAL_PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+
+        if let Some(helper) = editor.helper_mut() {
+            helper.load_words(&conn);
+        }
+
+        let input = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C cancels the current input, including any unfinished statement
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
             }
-            _ => {
-                if let Err(err) = run_sql_query(&conn, input) {
-                    eprintln!("{err}");
-                }
+            // Ctrl-D (end-of-input) exits, like `quit`
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                return Err(Fatal::new(
+                    EXIT_IO_ERROR,
+                    format!("Failed to read line. {}", err),
+                ))
             }
+        };
+
+        let input = input.trim(); // Remove surrounding whitespace
+
+        if !input.is_empty() {
+            let _ = editor.add_history_entry(input);
+        }
+
+        // Failed statements are reported as they run, and the prompt continues
+        if let LineOutcome::Exit = handle_line(
+            &conn,
+            &repos[0],
+            &args,
+            &mut settings,
+            &mut loaded_tips,
+            &mut buffer,
+            input,
+        ) {
+            break;
+        }
+    }
+
+    // Save the history for future sessions
+    if let Some(path) = &history_path {
+        if let Err(err) = editor.save_history(path) {
+            eprintln!("Failed to save history. {}", err);
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_signature_removes_pgp_signature() {
+        let message =
+            "Release 1.0\n\n-----BEGIN PGP SIGNATURE-----\n\niQEz\n-----END PGP SIGNATURE-----\n";
+
+        assert_eq!(strip_signature(message), "Release 1.0");
+    }
+
+    #[test]
+    fn strip_signature_removes_ssh_signature() {
+        let message =
+            "Release 1.0\n-----BEGIN SSH SIGNATURE-----\nU1NIU0lH\n-----END SSH SIGNATURE-----\n";
+
+        assert_eq!(strip_signature(message), "Release 1.0");
+    }
+
+    #[test]
+    fn strip_signature_removes_x509_signature() {
+        let message =
+            "Release 1.0\n-----BEGIN SIGNED MESSAGE-----\nMIAG\n-----END SIGNED MESSAGE-----\n";
+
+        assert_eq!(strip_signature(message), "Release 1.0");
+    }
+
+    #[test]
+    fn strip_signature_cuts_at_earliest_marker() {
+        let message = "Release 1.0\n-----BEGIN SSH SIGNATURE-----\nU1NIU0lH\n-----BEGIN PGP SIGNATURE-----\niQEz\n";
+
+        assert_eq!(strip_signature(message), "Release 1.0");
+    }
+
+    #[test]
+    fn strip_signature_keeps_unsigned_message() {
+        let message = "Release 1.0\n\nNo signature here\n";
+
+        assert_eq!(strip_signature(message), message);
+    }
+
+    #[test]
+    fn rest_of_input_keeps_inner_spaces() {
+        assert_eq!(
+            rest_of_input(
+                "  .view save  top  SELECT a,  b FROM t ",
+                &[".view", "save", "top"]
+            ),
+            "SELECT a,  b FROM t"
+        );
+    }
+
+    #[test]
+    fn word_start_skips_ascii_separator() {
+        assert_eq!(word_start("SELECT com", 10), 7);
+    }
+
+    #[test]
+    fn word_start_skips_multibyte_separator() {
+        let line = "SELECT ’com";
+
+        assert_eq!(&line[word_start(line, line.len())..], "com");
+    }
+
+    #[test]
+    fn word_start_is_line_start_without_separator() {
+        assert_eq!(word_start("com", 3), 0);
+    }
+
+    // Function to parse the author of a raw commit, as git2 won't create a signature with an
+    // empty name. The commit is written to an in-memory object database
+    fn parse_author(author: &[u8]) -> Option<String> {
+        let odb = git2::Odb::new().unwrap();
+        odb.add_new_mempack_backend(1).unwrap();
+        let repo = Repository::from_odb(odb).unwrap();
+
+        let mut raw = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\nauthor ".to_vec();
+        raw.extend_from_slice(author);
+        raw.extend_from_slice(b" 0 +0000\ncommitter C <c@example.com> 0 +0000\n\nmessage\n");
+        let id = repo.odb().unwrap().write(ObjectType::Commit, &raw).unwrap();
+
+        let commit = repo.find_commit(id).unwrap();
+        let name = signature_name(&commit.author());
+        name
+    }
+
+    #[test]
+    fn signature_name_falls_back_to_email() {
+        assert_eq!(
+            parse_author(b" <dev@example.com>"),
+            Some(String::from("dev@example.com"))
+        );
+    }
+
+    #[test]
+    fn signature_name_decodes_invalid_utf8_lossily() {
+        assert_eq!(
+            parse_author(b"Ren\xe9 <rene@example.com>"),
+            Some(String::from("Ren\u{fffd}"))
+        );
+    }
+
+    #[test]
+    fn signature_name_uses_name() {
+        assert_eq!(
+            parse_author(b"Dev <dev@example.com>"),
+            Some(String::from("Dev"))
+        );
+    }
+}